@@ -0,0 +1,39 @@
+use std::env::args_os;
+use std::fs::File;
+
+use photorec::{ReportXml, Coverage};
+
+fn main() {
+    let reports = args_os().skip(1).map(|fname| {
+        let lossy = fname.to_string_lossy();
+        eprintln!("Parsing file {0}", &lossy);
+        let f = File::open(&fname).expect(&lossy);
+        let report = ReportXml::parse(f).expect(&lossy);
+        (lossy.into_owned(), report)
+    }).collect::<Vec<_>>();
+    let volume = reports.iter().find_map(|(_, report)| report.volume());
+    let coverage = Coverage::new(reports.iter().map(|(fname, report)| {
+        (fname.clone(), report.iter().filter_map(|r| match r {
+            Ok(x) => Some(x),
+            Err(e) => { eprintln!("At {0}: {1}", &fname, e); None }
+        }).collect::<Vec<_>>())
+    }));
+
+    println!("claimed\t{}", coverage.claimed_bytes());
+    println!("multiply_claimed\t{}", coverage.multiply_claimed_bytes());
+    for (seg, files) in coverage.multiply_claimed() {
+        let files = files.iter().map(|o| format!("{}:{}", o.report, o.filename)).collect::<Vec<_>>().join(" ");
+        println!("overlap\t{}\t{}\t{}", seg.start, seg.end, files);
+    }
+    match volume {
+        Some(volume) => {
+            let gaps = coverage.gaps(&volume);
+            println!("volume\t{}\t{}", volume.start, volume.end);
+            println!("unclaimed\t{}", gaps.iter().map(|s| s.end - s.start).sum::<u64>());
+            for seg in gaps {
+                println!("gap\t{}\t{}", seg.start, seg.end);
+            }
+        },
+        None => eprintln!("No volume given in the reports, not looking for gaps"),
+    }
+}
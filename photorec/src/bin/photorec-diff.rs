@@ -0,0 +1,83 @@
+use std::env::args_os;
+use std::fs::File;
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use photorec::{ReportXml, FileDescription, DiffEntry, DiffKind, diff};
+
+fn parse_report(fname: &std::ffi::OsStr) -> Vec<(String, FileDescription)> {
+    let lossy = fname.to_string_lossy();
+    eprintln!("Parsing file {0}", &lossy);
+    let f = File::open(fname).expect(&lossy);
+    let report = ReportXml::parse(f).expect(&lossy);
+    report.iter().filter_map(|r| match r {
+        Ok(x) => Some(x),
+        Err(e) => { eprintln!("At {0}: {1}", &lossy, e); None }
+    }).collect()
+}
+
+fn side_to_json(side: &Option<(String, FileDescription)>) -> Value {
+    match side {
+        Some((name, desc)) => json!({
+            "name": name,
+            "filesize": desc.size(),
+            "byte_runs": desc.as_ref().iter().map(|br| json!({
+                "offset": br.file_offset,
+                "img_offset": br.disk_pos,
+                "len": br.len,
+            })).collect::<Vec<_>>(),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn entry_to_text(entry: &DiffEntry) -> String {
+    let name = |side: &Option<(String, FileDescription)>| side.as_ref().map(|x| x.0.clone()).unwrap_or_default();
+    match entry.kind {
+        DiffKind::OnlyInA => format!("{}\t{}", entry.kind, name(&entry.a)),
+        DiffKind::OnlyInB => format!("{}\t{}", entry.kind, name(&entry.b)),
+        _ => format!("{}\t{}\t{}", entry.kind, name(&entry.a), name(&entry.b)),
+    }
+}
+
+fn main() {
+    let mut json_output = false;
+    let mut fnames = Vec::new();
+    for arg in args_os().skip(1) {
+        if arg == "--json" { json_output = true; } else { fnames.push(arg); }
+    }
+    if fnames.len() != 2 {
+        eprintln!("Usage: photorec-diff [--json] <report_a.xml> <report_b.xml>");
+        std::process::exit(2);
+    }
+    let a = parse_report(&fnames[0]);
+    let b = parse_report(&fnames[1]);
+    let entries = diff(a, b);
+
+    let mut counts = BTreeMap::new();
+    for entry in entries.iter() {
+        *counts.entry(entry.kind).or_insert(0u64) += 1;
+    }
+    if json_output {
+        let out = json!({
+            "a": fnames[0].to_string_lossy(),
+            "b": fnames[1].to_string_lossy(),
+            "summary": counts.iter().map(|(k, v)| (k.to_string(), json!(v))).collect::<serde_json::Map<_, _>>(),
+            "entries": entries.iter().map(|e| json!({
+                "kind": e.kind.as_str(),
+                "a": side_to_json(&e.a),
+                "b": side_to_json(&e.b),
+            })).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&out).unwrap());
+    } else {
+        for entry in entries.iter() {
+            println!("{}", entry_to_text(entry));
+        }
+        println!();
+        for (kind, count) in counts.iter() {
+            println!("{}: {}", kind, count);
+        }
+    }
+}
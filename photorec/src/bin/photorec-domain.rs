@@ -0,0 +1,50 @@
+use std::env::args_os;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
+
+use photorec::{ReportXml, Mapfile};
+
+fn main() {
+    let mut it = args_os().skip(1).peekable();
+    // A file is selected if it has one of the types and contains one of the names, where no types
+    // (or names) given means any
+    let mut types = Vec::new();
+    let mut names = Vec::new();
+    loop {
+        if it.peek().map(|x| x == "--type") == Some(true) {
+            it.next();
+            types.push(it.next().unwrap());
+        } else if it.peek().map(|x| x == "--name") == Some(true) {
+            it.next();
+            names.push(it.next().unwrap().to_string_lossy().into_owned());
+        } else {
+            break;
+        }
+    }
+    let output_path = PathBuf::from(it.next().unwrap());
+    let reports = it.map(|fname| {
+        let lossy = fname.to_string_lossy();
+        println!("Parsing file {0}", &lossy);
+        let f = File::open(&fname).expect(&lossy);
+        let report = ReportXml::parse(f).expect(&lossy);
+        (lossy.into_owned(), report)
+    }).collect::<Vec<_>>();
+    let descs = reports.iter().flat_map(|(fname, report)| {
+        report.iter().filter_map(move |r| match r {
+            Ok(x) => Some(x),
+            Err(e) => { println!("At {0}: {1}", &fname, e); None }
+        })
+    }).filter(|(name, _)| {
+        let ext = Path::new(name).extension();
+        (types.is_empty() || types.iter().any(|t| ext == Some(OsStr::new(t))))
+            && (names.is_empty() || names.iter().any(|n| name.contains(n.as_str())))
+    }).map(|(_, desc)| desc).collect::<Vec<_>>();
+    println!("Selected {0} files", descs.len());
+
+    let end = descs.iter().flat_map(|d| d.as_ref().iter().map(|br| br.disk_pos + br.len)).max().unwrap_or(0);
+    let size = reports.iter().find_map(|(_, report)| report.image_size()).unwrap_or(0).max(end);
+    println!("Writing {0}", output_path.display());
+    let f = File::create(&output_path).unwrap();
+    Mapfile::domain_of(&descs, size).write(f).unwrap();
+}
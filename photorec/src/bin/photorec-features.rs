@@ -0,0 +1,45 @@
+use std::env::args_os;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+use photorec::{ReportXml, DiskIndex, parse_features, annotate};
+
+fn main() {
+    let mut it = args_os().skip(1);
+    let features_fname = it.next().unwrap();
+    let reports = it.map(|fname| {
+        let lossy = fname.to_string_lossy();
+        eprintln!("Parsing file {0}", &lossy);
+        let f = File::open(&fname).expect(&lossy);
+        let report = ReportXml::parse(f).expect(&lossy);
+        (lossy.into_owned(), report)
+    }).collect::<Vec<_>>();
+    let index = DiskIndex::new(reports.iter().map(|(fname, report)| {
+        (fname.clone(), report.iter().filter_map(|r| match r {
+            Ok(x) => Some(x),
+            Err(e) => { eprintln!("At {0}: {1}", &fname, e); None }
+        }).collect::<Vec<_>>())
+    }));
+
+    let lossy = features_fname.to_string_lossy();
+    let f = File::open(&features_fname).expect(&lossy);
+    let features = parse_features(BufReader::new(f)).filter_map(|r| match r {
+        Ok(x) => Some(x),
+        Err(e) => { eprintln!("At {0}: {1}", &lossy, e); None }
+    });
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let (mut claimed, mut unclaimed) = (0, 0);
+    for (feature, hits) in annotate(&index, features) {
+        if hits.is_empty() {
+            unclaimed += 1;
+            writeln!(out, "{}\t{}\tunclaimed", feature.path, feature.feature).unwrap();
+        } else {
+            claimed += 1;
+        }
+        for hit in hits {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}", feature.path, feature.feature, hit.file.report, hit.file.filename, hit.file_offset).unwrap();
+        }
+    }
+    eprintln!("{} features in recovered files, {} in unclaimed space", claimed, unclaimed);
+}
@@ -0,0 +1,51 @@
+use std::env::args_os;
+use std::fs::File;
+use std::path::PathBuf;
+use std::ffi::OsStr;
+
+use photorec::{ReportXml, Coverage, ByteRun, FileDescription, Mapfile};
+
+fn main() {
+    let mut it = args_os().skip(1).peekable();
+    let min_size = if it.peek().map(|x| x == "--min-size") == Some(true) {
+        it.next();
+        it.next().unwrap().to_string_lossy().parse::<u64>().expect("--min-size")
+    } else {
+        0
+    };
+    let output_path = PathBuf::from(it.next().unwrap());
+    let reports = it.map(|fname| {
+        let lossy = fname.to_string_lossy();
+        println!("Parsing file {0}", &lossy);
+        let f = File::open(&fname).expect(&lossy);
+        let report = ReportXml::parse(f).expect(&lossy);
+        (lossy.into_owned(), report)
+    }).collect::<Vec<_>>();
+    let volume = reports.iter().find_map(|(_, report)| report.volume()).expect("No volume given in the reports");
+    let sectorsize = reports.iter().find_map(|(_, report)| report.sectorsize()).unwrap_or(512);
+    let coverage = Coverage::new(reports.iter().map(|(fname, report)| {
+        (fname.clone(), report.iter().filter_map(|r| match r {
+            Ok(x) => Some(x),
+            Err(e) => { println!("At {0}: {1}", &fname, e); None }
+        }).collect::<Vec<_>>())
+    }));
+    let gaps = coverage.aligned_gaps(&volume, sectorsize, min_size);
+    println!("Found {0} gaps, of {1} bytes", gaps.len(), gaps.iter().map(|s| s.end - s.start).sum::<u64>());
+
+    println!("Writing {0}", output_path.display());
+    let f = File::create(&output_path).unwrap();
+    if output_path.extension() == Some(OsStr::new("xml")) {
+        let mut rx: ReportXml = gaps.into_iter().map(|seg| {
+            let len = seg.end - seg.start;
+            let desc = FileDescription::new(len, vec![ByteRun { file_offset: 0, disk_pos: seg.start, len }]).unwrap();
+            (format!("gap_{}_{}", seg.start, seg.end), desc)
+        }).collect();
+        // The source giving the volume, so that tools reading the gaps back use the same one
+        let (_, from) = reports.iter().find(|(_, report)| report.volume().is_some()).unwrap();
+        rx.copy_source(from);
+        rx.write(f).unwrap();
+    } else {
+        let size = reports.iter().find_map(|(_, report)| report.image_size()).unwrap_or(0).max(volume.end);
+        Mapfile::domain(gaps, size).write(f).unwrap();
+    }
+}
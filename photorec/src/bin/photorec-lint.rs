@@ -0,0 +1,34 @@
+use std::env::args_os;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::exit;
+
+use photorec::{ReportXml, Severity, lint};
+
+fn main() {
+    let mut it = args_os().skip(1).peekable();
+    // Problems less severe are left out
+    let min_severity = if it.peek().map(|x| x == "--errors") == Some(true) {
+        it.next();
+        Severity::Error
+    } else {
+        Severity::Warning
+    };
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut errors = 0;
+    for fname in it {
+        let lossy = fname.to_string_lossy();
+        eprintln!("Parsing file {0}", &lossy);
+        let f = File::open(&fname).expect(&lossy);
+        let report = ReportXml::parse(f).expect(&lossy);
+        let lints = lint(&report);
+        errors += lints.iter().filter(|l| l.severity == Severity::Error).count();
+        for l in lints.iter().filter(|l| l.severity >= min_severity) {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}", &lossy, l.index, l.file_name.as_deref().unwrap_or("-"), l.severity, l.problem).unwrap();
+        }
+        eprintln!("{}: {} problems", &lossy, lints.len());
+    }
+    out.flush().unwrap();
+    if errors > 0 { exit(1); }
+}
@@ -1,34 +1,107 @@
-
 use std::env::args_os;
 use std::fs::File;
-use std::fmt::{Formatter, Error as FmtError, Display};
-use std::{path::Path, iter::FromIterator};
+use std::path::{Path, PathBuf};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ffi::OsStr;
+
+use serde_json::json;
+
+use photorec::{ReportXml, FileDescription, MergeConflict, merge_with, union, intersection, difference, symmetric_difference};
+use photorec::{ByteRunsReader, Candidate, ContentCheck, Desc, DropBoth, FewerFragments, FirstReport, LargerFilesize, Resolver, Split};
 
-use photorec::{ReportXml, FileDescription, ByteRun};
-use segment_tree::{SegmentArrayTree, SegmentArrayTreeError, AddStatus};
+type SetOp = fn(Vec<Vec<(String, FileDescription)>>) -> ReportXml;
 
-#[derive(Debug)]
-struct FileDescriptionWithContext<'a> {
-    desc: FileDescription,
-    xml_name: &'a str,
-    desc_name: String,
+/// The single-report set operations, selected by a leading flag instead of the default
+/// superset-preferring merge into several reports.
+fn set_op(flag: &str) -> Option<SetOp> {
+    match flag {
+        "--union" => Some(union),
+        "--intersection" => Some(intersection),
+        "--difference" => Some(difference),
+        "--symmetric-difference" => Some(symmetric_difference),
+        _ => None,
+    }
+}
+
+/// Whether the file starts with a JPEG SOI marker and ends with an EOI marker.
+fn looks_like_jpeg(image: &File, desc: &FileDescription) -> bool {
+    let mut brr = ByteRunsReader::new(image, desc.at_pos(0));
+    let (mut start, mut end) = ([0; 3], [0; 2]);
+    brr.read_exact(&mut start).is_ok() && brr.seek(SeekFrom::End(-2)).is_ok() && brr.read_exact(&mut end).is_ok()
+        && start == [0xff, 0xd8, 0xff] && end == [0xff, 0xd9]
+}
+
+/// The conflict resolution strategy given to `--resolve`. `content=<image>` keeps the
+/// description which looks like a whole JPEG in the given image.
+fn resolver(name: &str) -> Box<dyn Resolver> {
+    match name {
+        "split" => Box::new(Split),
+        "larger-filesize" => Box::new(LargerFilesize),
+        "fewer-fragments" => Box::new(FewerFragments),
+        "first-report" => Box::new(FirstReport),
+        "drop-both" => Box::new(DropBoth),
+        _ => match name.strip_prefix("content=") {
+            Some(image) => {
+                let image = File::open(image).expect(image);
+                Box::new(ContentCheck(move |c: &Candidate<'_>| looks_like_jpeg(&image, c.desc)))
+            },
+            None => panic!("Unknown resolution strategy {}", name),
+        },
+    }
 }
 
-impl<'a> Display for FileDescriptionWithContext<'a> {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        f.write_str(self.xml_name)?;
-        f.write_str(":")?;
-        f.write_str(self.desc_name.as_ref())
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
     }
 }
 
-impl<'a> AsRef<[ByteRun]> for FileDescriptionWithContext<'a> {
-    fn as_ref(&self) -> &[ByteRun] { self.desc.as_ref() }
+/// Writes the conflicts as CSV if the path ends with `.csv`, and as JSON otherwise.
+fn write_conflicts(path: &Path, conflicts: &[MergeConflict]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    if path.extension() == Some(OsStr::new("csv")) {
+        writeln!(f, "report_a,fileobject_a,report_b,fileobject_b,kind,overlaps,resolution")?;
+        for c in conflicts {
+            let overlaps = c.overlaps.iter().map(|s| format!("{}-{}", s.start, s.end)).collect::<Vec<_>>().join(" ");
+            writeln!(f, "{},{},{},{},{},{},{}", csv_field(&c.report_a), csv_field(&c.name_a),
+                     csv_field(&c.report_b), csv_field(&c.name_b), c.kind, overlaps,
+                     c.resolution.as_str())?;
+        }
+    } else {
+        let out = conflicts.iter().map(|c| json!({
+            "report_a": c.report_a,
+            "fileobject_a": c.name_a,
+            "report_b": c.report_b,
+            "fileobject_b": c.name_b,
+            "kind": c.kind.as_str(),
+            "overlaps": c.overlaps.iter().map(|s| json!({ "start": s.start, "end": s.end })).collect::<Vec<_>>(),
+            "resolution": c.resolution.as_str(),
+        })).collect::<Vec<_>>();
+        serde_json::to_writer_pretty(&mut f, &out)?;
+        writeln!(f)?;
+    }
+    Ok(())
 }
 
 fn main() {
-    let mut sats = vec![SegmentArrayTree::new()];
-    let mut it = args_os().skip(1);
+    let mut it = args_os().skip(1).peekable();
+    let op = it.peek().and_then(|x| x.to_str()).and_then(|x| Some((x.trim_start_matches('-').to_owned(), set_op(x)?)));
+    if op.is_some() { it.next(); }
+    let mut conflicts_path = None;
+    let mut resolve: Box<dyn Resolver> = Box::new(Split);
+    loop {
+        if it.peek().map(|x| x == "--conflicts") == Some(true) {
+            it.next();
+            conflicts_path = Some(PathBuf::from(it.next().unwrap()));
+        } else if it.peek().map(|x| x == "--resolve") == Some(true) {
+            it.next();
+            resolve = resolver(&it.next().unwrap().to_string_lossy());
+        } else {
+            break;
+        }
+    }
     // let temp = it.next_back().unwrap();
     let temp = it.next().unwrap();
     let output_dir = Path::new(&temp);
@@ -39,63 +112,29 @@ fn main() {
         let report = ReportXml::parse(f).expect(&lossy);
         (lossy.into_owned(), report)
     }).collect::<Vec<_>>();
-    for (fname, report) in reports.iter() {
-        println!();
-        println!("Adding file {0}", &fname);
-        for r in report.iter() {
-            match r {
-                Ok((name, desc)) => {
-                    if !name.ends_with(".jpg") { continue; }
-                    let mut fdwc = FileDescriptionWithContext {
-                        desc,
-                        xml_name: fname.as_ref(),
-                        desc_name: name,
-                    };
-                    let mut add_new_tree = false;
-                    let last = sats.len() - 1;
-                    for (num, sat) in sats.iter_mut().enumerate() {
-                        if num == last {
-                            add_new_tree = true;
-                        }
-                        match sat.add(fdwc) {
-                            Err((_fdwc, e)) => {
-                                fdwc = _fdwc;
-                                let (fdwc1, fdwc2) = match e {
-                                    SegmentArrayTreeError::IntersectingSegment(idx) =>
-                                        (sat.get_by_idx(idx), None),
-                                    SegmentArrayTreeError::OverlappingSegmentArrays(idx1, idx2) =>
-                                        (sat.get_by_idx(idx1), Some(sat.get_by_idx(idx2))),
-                                    SegmentArrayTreeError::IncompatibleSegmentArrays(idx) =>
-                                        (sat.get_by_idx(idx), None),
-                                };
-                                if let Some(fdwc2) = fdwc2 {
-                                    println!("On tree {num}, got error {e}, with relevant file descriptions at {0}, {1}, {2}", fdwc, fdwc1, fdwc2, e = e, num = num);
-                                } else {
-                                    println!("On tree {num}, got error {e}, with relevant file descriptions at {0}, {1}", fdwc, fdwc1, e = e, num = num);
-                                };
-                            } 
-                            Ok(AddStatus::Replaced(fdwc1)) => {
-                                println!("On tree {num}, replaced file description at {fdwc}", num = num, fdwc = fdwc1);
-                                break;
-                            }
-                            _ => { break; }
-                        }
-                    }
-
-                    if add_new_tree {
-                        sats.push(SegmentArrayTree::new());
-                    }
-                }
-                Err(e) => {
-                    println!("At {0}: {1}", &fname, e);
-                }
-            }
-        }
+    let descs = reports.iter().map(|(fname, report)| {
+        (fname.clone(), report.iter().filter_map(|r| match r {
+            Ok((name, desc)) => if name.ends_with(".jpg") { Some((name, desc)) } else { None },
+            Err(e) => { println!("At {0}: {1}", &fname, e); None }
+        }).collect::<Vec<_>>())
+    }).collect::<Vec<_>>();
+    if let Some((op_name, op)) = op {
+        let output_path = output_dir.join(format!("{}.xml", op_name));
+        println!("Writing {0}", output_path.display());
+        let f = File::create(output_path).unwrap();
+        op(descs.into_iter().map(|(_, x)| x).collect()).write(f).unwrap();
+        return;
+    }
+    let merged = merge_with(descs, resolve);
+    println!("Found {0} conflicts", merged.conflicts.len());
+    if let Some(conflicts_path) = conflicts_path {
+        println!("Writing {0}", conflicts_path.display());
+        write_conflicts(&conflicts_path, &merged.conflicts).unwrap();
     }
-    for (num, sat) in sats.into_iter().enumerate() {
+    for (num, rx) in merged.reports.into_iter().enumerate() {
         let output_path = output_dir.join(format!("report{}.xml", num));
+        println!("Writing {0}", output_path.display());
         let f = File::create(output_path).unwrap();
-        let rx = ReportXml::from_iter(sat.into_iter().map(|fdwc| (fdwc.desc_name, fdwc.desc)));
         rx.write(f).unwrap();
     }
 }
@@ -1,45 +1,183 @@
 use std::env::args_os;
 use std::fs::{File, create_dir, OpenOptions};
 use std::path::{Path, PathBuf};
-use std::io::{Read, Write};
+use std::io::{BufReader, Read, Write, Seek, SeekFrom};
 use std::ffi::OsStr;
+use std::time::Duration;
 
-use photorec::{ReportXml, ByteRunsReader, Desc};
+use segment_tree::Segment;
+
+use photorec::{ReportXml, ByteRunsReader, Desc, Mapfile, TolerantReader, ReadPolicy, Fill, Image, ImageResolver, FileDescription, Policy, schedule, extract};
+
+fn copy<R: Read>(mut reader: R, file: &mut File) {
+    let mut buf = [0; 1024];
+    loop {
+        let x = reader.read(&mut buf).unwrap();
+        if x == 0 { break; }
+        file.write_all(&buf[..x]).unwrap();
+    }
+}
+
+/// Writes the damaged ranges of file offsets to a sidecar next to the file, if any.
+fn write_damaged(output_file: &Path, desc: &FileDescription, damaged: &[Segment<u64>]) {
+    let damaged = damaged.iter().flat_map(|s| desc.file_ranges(s)).collect::<Vec<_>>();
+    if damaged.is_empty() { return; }
+    let mut sidecar_name = output_file.file_name().unwrap().to_owned();
+    sidecar_name.push(".damaged");
+    let sidecar = output_file.with_file_name(sidecar_name);
+    println!("Writing {} damaged ranges to {:?}", damaged.len(), &sidecar);
+    let mut sidecar = File::create(sidecar).unwrap();
+    for s in damaged {
+        writeln!(sidecar, "{}\t{}", s.start, s.end).unwrap();
+    }
+}
+
+/// Writes the holes of a file extracted with --elevator, which only writes the runs, as
+/// ByteRunsReader fills them; a file ending in a hole is extended to its size.
+fn fill_holes(output_file: &Path, desc: &FileDescription, fill: &Fill) {
+    let mut file = OpenOptions::new().write(true).open(output_file).unwrap();
+    file.set_len(desc.size()).unwrap();
+    let p = match fill {
+        Fill::Pattern(p) if !p.is_empty() => p,
+        _ => { return; },
+    };
+    for hole in desc.missing_ranges() {
+        file.seek(SeekFrom::Start(hole.start)).unwrap();
+        let mut hole_off = 0;
+        while hole_off < hole.end - hole.start {
+            let n = (hole.end - hole.start - hole_off).min(1 << 20);
+            let chunk = (hole_off..hole_off + n).map(|i| p[(i % p.len() as u64) as usize]).collect::<Vec<_>>();
+            file.write_all(&chunk).unwrap();
+            hole_off += n;
+        }
+    }
+}
 
 fn main() {
-    let mut it = args_os().skip(1);
+    let mut it = args_os().skip(1).peekable();
+    let mut mapfile = None;
+    // Whether to skip files not completely rescued according to the mapfile, or tag them
+    let mut skip_incomplete = false;
+    // Set if reading should go on past read errors
+    let mut tolerant: Option<ReadPolicy> = None;
+    // Whether to read the files of all the reports in a single pass over the disk
+    let mut elevator = false;
+    // Whether to repair fileobjects whose runs don't tile the file, rather than skip them
+    let mut repair = false;
+    let mut resolver = ImageResolver::new();
+    loop {
+        if it.peek().map(|x| x == "--mapfile") == Some(true) {
+            it.next();
+            let fname = it.next().unwrap();
+            let lossy = fname.to_string_lossy();
+            let f = File::open(&fname).expect(&lossy);
+            mapfile = Some(Mapfile::parse(BufReader::new(f)).expect(&lossy));
+        } else if it.peek().map(|x| x == "--incomplete") == Some(true) {
+            it.next();
+            skip_incomplete = match it.next().unwrap().to_str() {
+                Some("skip") => true,
+                Some("tag") => false,
+                x => panic!("Unknown --incomplete mode {:?}", x),
+            };
+        } else if it.peek().map(|x| x == "--tolerant") == Some(true) {
+            it.next();
+            tolerant.get_or_insert_with(ReadPolicy::default);
+        } else if it.peek().map(|x| x == "--retries") == Some(true) {
+            it.next();
+            tolerant.get_or_insert_with(ReadPolicy::default).retries = it.next().unwrap().to_string_lossy().parse().expect("--retries");
+        } else if it.peek().map(|x| x == "--backoff-ms") == Some(true) {
+            it.next();
+            let ms = it.next().unwrap().to_string_lossy().parse().expect("--backoff-ms");
+            tolerant.get_or_insert_with(ReadPolicy::default).backoff = Duration::from_millis(ms);
+        } else if it.peek().map(|x| x == "--fill-pattern") == Some(true) {
+            it.next();
+            let pattern = it.next().unwrap().to_string_lossy().into_owned().into_bytes();
+            tolerant.get_or_insert_with(ReadPolicy::default).fill = Fill::Pattern(pattern);
+        } else if it.peek().map(|x| x == "--elevator") == Some(true) {
+            it.next();
+            elevator = true;
+        } else if it.peek().map(|x| x == "--repair") == Some(true) {
+            it.next();
+            repair = true;
+        } else if it.peek().map(|x| x == "--map") == Some(true) {
+            it.next();
+            let rule = it.next().unwrap().to_string_lossy().into_owned();
+            let (from, to) = rule.split_once('=').expect("--map <from>=<to>");
+            resolver.add_rule(from, to);
+        } else {
+            break;
+        }
+    }
     // let temp = it.next_back().unwrap();
     let temp = it.next().unwrap();
     let output_dir = Path::new(&temp);
+    // The disk to read reports from, unless their image is mapped; with "-", each report is read
+    // from the image it records
     let temp = it.next().unwrap();
-    let volume_fname = Path::new(&temp);
-    let volume = File::open(volume_fname).unwrap();
+    resolver.set_default(Some(temp).filter(|x| x != "-").map(PathBuf::from));
     let reports = it.map(|fname| {
         let lossy = fname.to_string_lossy();
         println!("Parsing file {0}", &lossy);
         let fname = PathBuf::new().join(&fname);
         let f = File::open(&fname).expect(&lossy);
-        let report = ReportXml::parse(f).expect(&lossy);
+        let mut report = ReportXml::parse(f).expect(&lossy);
+        if repair { report.set_policy(Policy::repair()); }
         (fname, report)
     }).collect::<Vec<_>>();
+    // The images to read, the (empty) output files and their descriptions, to be read together
+    // with --elevator
+    let mut jobs: Vec<(PathBuf, PathBuf, FileDescription)> = Vec::new();
     for (fname, report) in reports.into_iter() {
+        #[allow(clippy::needless_borrows_for_generic_args)]
         let output_sub_dir = output_dir.join(&fname.file_stem().unwrap());
         println!("Creating dir {:?}", &output_sub_dir);
         create_dir(&output_sub_dir).unwrap();
-        for r in report.iter() {
+        if let Some(ref mut policy) = tolerant {
+            policy.sectorsize = report.sectorsize().unwrap_or(512);
+        }
+        let volume_path = resolver.resolve(report.image_filename().map(String::as_str))
+            .unwrap_or_else(|| panic!("No image for {}", fname.display()));
+        let mut volume = if elevator { None } else {
+            println!("Opening image {:?}", &volume_path);
+            Some(Image::open(&volume_path).unwrap())
+        };
+        for r in report.iter_with_repairs() {
             match r {
-                Ok((name, desc)) => {
+                Ok((name, desc, repairs)) => {
                     let name = Path::new(&name);
                     if name.extension() != Some(OsStr::new("jpg")) { continue; }
-                    let output_file = output_sub_dir.join(name.file_name().unwrap());
+                    for r in repairs.iter() {
+                        println!("Repaired {:?}: {}", name, r);
+                    }
+                    let complete = mapfile.as_ref().map_or(true, |m| m.rescue_status(&desc).is_complete());
+                    if !complete && skip_incomplete {
+                        println!("Skipping incomplete file {:?}", name);
+                        continue;
+                    }
+                    let file_name = name.file_name().unwrap().to_string_lossy();
+                    let output_file = if complete {
+                        output_sub_dir.join(&*file_name)
+                    } else {
+                        output_sub_dir.join(format!("incomplete_{}", file_name))
+                    };
+                    let mut file = OpenOptions::new().write(true).create_new(true).open(&output_file).unwrap();
+                    if elevator {
+                        println!("Scheduling file {:?}", &output_file);
+                        jobs.push((volume_path.clone(), output_file, desc));
+                        continue;
+                    }
                     println!("Writing file {:?}", &output_file);
-                    let mut file = OpenOptions::new().write(true).create_new(true).open(output_file).unwrap();
-                    let mut brr = ByteRunsReader::new(&volume, desc.at_pos(0));
-                    let mut buf = [0; 1024];
-                    loop {
-                        let x = brr.read(&mut buf).unwrap();
-                        if x == 0 { break; }
-                        file.write(&buf[..x]).unwrap();
+                    let volume = volume.as_mut().unwrap();
+                    match tolerant {
+                        None => copy(ByteRunsReader::new(&mut *volume, desc.at_pos(0)), &mut file),
+                        Some(ref policy) => {
+                            let mut tr = TolerantReader::new(&mut *volume, policy.clone());
+                            let mut reader = ByteRunsReader::new(&mut tr, desc.at_pos(0));
+                            // Holes left by repairs are filled like damaged sectors
+                            reader.set_fill(policy.fill.clone());
+                            copy(reader, &mut file);
+                            write_damaged(&output_file, &desc, &tr.take_damaged());
+                        },
                     }
                 }
                 Err(e) => {
@@ -48,4 +186,44 @@ fn main() {
             }
         }
     }
+    if !elevator { return; }
+
+    let mut volume_paths: Vec<PathBuf> = Vec::new();
+    for (v, _, _) in jobs.iter() {
+        if !volume_paths.contains(v) { volume_paths.push(v.clone()); }
+    }
+    for volume_path in volume_paths {
+        println!("Opening image {:?}", &volume_path);
+        let mut volume = Image::open(&volume_path).unwrap();
+        let jobs = jobs.iter().filter(|(v, _, _)| *v == volume_path).map(|(_, f, d)| (f, d)).collect::<Vec<_>>();
+        let regions = schedule(jobs.iter().map(|(_, desc)| *desc));
+        println!("Reading {} regions for {} files", regions.len(), jobs.len());
+        // Files are reopened as needed, as there may be more of them than we can keep open; but
+        // consecutive chunks usually go to the same file
+        let mut current: Option<(usize, File)> = None;
+        let mut sink = |idx: usize, offset: u64, data: &[u8]| {
+            if current.as_ref().map(|(i, _)| *i) != Some(idx) {
+                current = Some((idx, OpenOptions::new().write(true).open(jobs[idx].0)?));
+            }
+            let file = &mut current.as_mut().unwrap().1;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(data)
+        };
+        match tolerant {
+            None => extract(&mut volume, &regions, 1 << 20, &mut sink).unwrap(),
+            Some(ref policy) => {
+                let mut tr = TolerantReader::new(&mut volume, policy.clone());
+                extract(&mut tr, &regions, 1 << 20, &mut sink).unwrap();
+                let damaged = tr.take_damaged();
+                for (output_file, desc) in jobs.iter() {
+                    write_damaged(output_file, desc, &damaged);
+                }
+            },
+        }
+        // Holes left by repairs are filled like damaged sectors, as without --elevator
+        let fill = tolerant.as_ref().map_or(Fill::Zeros, |policy| policy.fill.clone());
+        for (output_file, desc) in jobs.iter() {
+            fill_holes(output_file, desc, &fill);
+        }
+    }
 }
@@ -0,0 +1,52 @@
+use std::env::args_os;
+use std::fs::File;
+
+use photorec::ReportXml;
+
+fn parse_report(fname: &std::ffi::OsStr) -> ReportXml {
+    let lossy = fname.to_string_lossy();
+    println!("Parsing file {0}", &lossy);
+    let f = File::open(fname).expect(&lossy);
+    ReportXml::parse(f).expect(&lossy)
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: photorec-rebase (--delta <bytes> | --to <report.xml>) <report.xml> <output.xml>");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut it = args_os().skip(1).peekable();
+    // Either a delta given, or the report whose offsets to match
+    let mut delta: Option<i64> = None;
+    let mut to = None;
+    loop {
+        if it.peek().map(|x| x == "--delta") == Some(true) {
+            it.next();
+            delta = Some(it.next().unwrap_or_else(|| usage()).to_string_lossy().parse().expect("--delta"));
+        } else if it.peek().map(|x| x == "--to") == Some(true) {
+            it.next();
+            to = Some(parse_report(&it.next().unwrap_or_else(|| usage())));
+        } else {
+            break;
+        }
+    }
+    let (input, output) = match (it.next(), it.next(), delta.is_some() != to.is_some()) {
+        (Some(input), Some(output), true) => (input, output),
+        _ => usage(),
+    };
+    let mut report = parse_report(&input);
+    let delta = match to {
+        None => delta.unwrap(),
+        Some(ref to) => report.volume_delta(to).expect("Both reports must give volumes of the same size"),
+    };
+    println!("Moving by {} bytes", delta);
+    report.rebase(delta).unwrap();
+    if let Some(ref to) = to {
+        // The offsets now refer to the image of the other report, which its source describes
+        report.copy_source(to);
+    }
+    println!("Writing {}", output.to_string_lossy());
+    let f = File::create(&output).unwrap();
+    report.write(f).unwrap();
+}
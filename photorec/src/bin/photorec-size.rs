@@ -1,21 +1,49 @@
 use std::env::args_os;
 use std::fs::File;
+use std::io::BufReader;
 
-use photorec::{ReportXml, ReportXmlError, FileDescriptionError};
+use photorec::{ReportXml, ReportXmlError, FileDescriptionError, Mapfile, RescueStatus, BlockStatus, Image, PartitionTable};
 
 fn main() {
-    let reports = args_os().skip(1).map(|fname| {
+    let mut it = args_os().skip(1).peekable();
+    let mut mapfile = None;
+    // The image to read the partition table from
+    let mut partitions_image = None;
+    loop {
+        if it.peek().map(|x| x == "--mapfile") == Some(true) {
+            it.next();
+            let fname = it.next().unwrap();
+            let lossy = fname.to_string_lossy();
+            let f = File::open(&fname).expect(&lossy);
+            mapfile = Some(Mapfile::parse(BufReader::new(f)).expect(&lossy));
+        } else if it.peek().map(|x| x == "--partitions") == Some(true) {
+            it.next();
+            partitions_image = it.next();
+        } else {
+            break;
+        }
+    }
+    let reports = it.map(|fname| {
         let lossy = fname.to_string_lossy();
         println!("Parsing file {0}", &lossy);
         let f = File::open(&fname).expect(&lossy);
         let report = ReportXml::parse(f).expect(&lossy);
         (lossy.into_owned(), report)
     }).collect::<Vec<_>>();
+    let table = partitions_image.and_then(|fname| {
+        let lossy = fname.to_string_lossy();
+        let sectorsize = reports.iter().find_map(|(_, report)| report.sectorsize()).unwrap_or(512);
+        let mut image = Image::open(&fname).expect(&lossy);
+        let table = PartitionTable::read(&mut image, sectorsize).expect(&lossy);
+        if table.is_none() { println!("No partition table in {}", &lossy); }
+        table
+    });
     for (fname, report) in reports.iter() {
+        #[allow(clippy::unnecessary_fold)]
         let size = report.iter().filter_map(|x| {
             match x {
                 Ok((s, fd)) => if s.ends_with(".jpg") { Some(fd.size()) } else { None }
-                Err(ReportXmlError::BadFileDescription { file_name: ref s, source: ref e }) => {
+                Err(ReportXmlError::BadFileDescription { file_name: ref s, source: ref e, .. }) => {
                     if s.ends_with(".jpg") { match e {
                         FileDescriptionError::Empty => None,
                         _ => { x.unwrap(); unreachable!() } // We panic anyway
@@ -27,7 +55,7 @@ fn main() {
         let count = report.iter().filter_map(|x| {
             match x {
                 Ok((s, _)) => if s.ends_with(".jpg") { Some(()) } else { None }
-                Err(ReportXmlError::BadFileDescription { file_name: ref s, source: ref e }) => {
+                Err(ReportXmlError::BadFileDescription { file_name: ref s, source: ref e, .. }) => {
                     if s.ends_with(".jpg") { match e {
                         FileDescriptionError::Empty => None,
                         _ => { x.unwrap(); unreachable!() } // We panic anyway
@@ -37,5 +65,39 @@ fn main() {
             }
         }).count();
         println!("{}: {} entries, {} bytes", fname, count, size);
+        if let Some(ref mapfile) = mapfile {
+            let mut total = RescueStatus::default();
+            let mut incomplete = 0;
+            for (_, desc) in report.iter().filter_map(|x| x.ok()).filter(|(s, _)| s.ends_with(".jpg")) {
+                let status = mapfile.rescue_status(&desc);
+                if !status.is_complete() { incomplete += 1; }
+                total += status;
+            }
+            println!("  {} incomplete entries; {:.2}% finished, {:.2}% non-tried, {:.2}% bad-sector, {:.2}% non-trimmed or non-scraped",
+                     incomplete, 100.0 * total.fraction(BlockStatus::Finished), 100.0 * total.fraction(BlockStatus::NonTried),
+                     100.0 * total.fraction(BlockStatus::BadSector),
+                     100.0 * (total.fraction(BlockStatus::NonTrimmed) + total.fraction(BlockStatus::NonScraped)));
+        }
+        if let Some(ref table) = table {
+            // By the index of the partition holding all the runs of the file, if there is one
+            let mut counts = vec![(0, 0); table.partitions.len() + 1];
+            for (_, desc) in report.iter().filter_map(|x| x.ok()).filter(|(s, _)| s.ends_with(".jpg")) {
+                let mut parts = desc.as_ref().iter().map(|br| {
+                    table.partition_of(br).map(|p| table.partitions.iter().position(|q| q == p).unwrap())
+                });
+                let first = parts.next().flatten();
+                let idx = match first {
+                    Some(idx) if parts.all(|p| p == first) => idx,
+                    _ => table.partitions.len(),
+                };
+                counts[idx].0 += 1;
+                counts[idx].1 += desc.size();
+            }
+            for (partition, (count, size)) in table.partitions.iter().zip(counts.iter()) {
+                println!("  partition {}: {} entries, {} bytes", partition, count, size);
+            }
+            let (count, size) = counts[table.partitions.len()];
+            println!("  outside or across partitions: {} entries, {} bytes", count, size);
+        }
     }
 }
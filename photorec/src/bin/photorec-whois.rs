@@ -0,0 +1,56 @@
+use std::env::args_os;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use photorec::{ReportXml, DiskIndex};
+
+/// Parses a decimal number, or a hexadecimal one starting with `0x`.
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn main() {
+    let mut it = args_os().skip(1).peekable();
+    let sectors = it.peek().map(|x| x == "--sectors") == Some(true);
+    if sectors { it.next(); }
+    let reports = it.map(|fname| {
+        let lossy = fname.to_string_lossy();
+        eprintln!("Parsing file {0}", &lossy);
+        let f = File::open(&fname).expect(&lossy);
+        let report = ReportXml::parse(f).expect(&lossy);
+        (lossy.into_owned(), report)
+    }).collect::<Vec<_>>();
+    let sectorsize = reports.iter().find_map(|(_, report)| report.sectorsize()).unwrap_or(512);
+    let index = DiskIndex::new(reports.iter().map(|(fname, report)| {
+        (fname.clone(), report.iter().filter_map(|r| match r {
+            Ok(x) => Some(x),
+            Err(e) => { eprintln!("At {0}: {1}", &fname, e); None }
+        }).collect::<Vec<_>>())
+    }));
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let pos = match parse_number(line) {
+            Some(x) if sectors => match x.checked_mul(sectorsize) {
+                Some(pos) => pos,
+                None => { eprintln!("Sector {0} is past the largest offset", line); continue; }
+            },
+            Some(x) => x,
+            None => { eprintln!("Bad offset {0}", line); continue; }
+        };
+        let hits = index.lookup(pos);
+        if hits.is_empty() {
+            writeln!(out, "{}\tunclaimed", pos).unwrap();
+        }
+        for hit in hits {
+            writeln!(out, "{}\t{}\t{}\t{}", pos, hit.file.report, hit.file.filename, hit.file_offset).unwrap();
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//
+// A map of the disk regions claimed by the fileobjects of one or more reports,
+// for judging how much of the volume was recovered.
+//
+use segment_tree::{Segment, SegmentTree};
+
+use super::file_description::{ByteRun, FileDescription};
+use super::report::Origin;
+use super::num::div_ceil;
+
+/// Splits the disk into disjoint regions, each claimed by the same values throughout, given the
+/// runs claiming it with their values. Regions claimed by nothing are left out; ones with the same
+/// values one after another are coalesced.
+pub(crate) fn sweep<T: Ord + Clone + std::fmt::Debug>(runs: Vec<(ByteRun, T)>) -> SegmentTree<u64, Vec<T>> {
+    // Each run adds its value at its start, and removes it at its end
+    let mut events: Vec<(u64, bool, T)> = Vec::new();
+    for (br, x) in runs.into_iter().filter(|(br, _)| br.len > 0) {
+        events.push((br.disk_pos, true, x.clone()));
+        events.push((br.disk_pos.saturating_add(br.len), false, x));
+    }
+    events.sort_unstable();
+
+    let mut tree = SegmentTree::new();
+    let mut claimers: Vec<T> = Vec::new();
+    let mut cur: Option<(u64, Vec<T>)> = None;
+    let mut it = events.into_iter().peekable();
+    while let Some(pos) = it.peek().map(|e| e.0) {
+        while let Some((_, add, x)) = it.next_if(|e| e.0 == pos) {
+            if add {
+                claimers.push(x);
+            } else if let Some(i) = claimers.iter().position(|y| *y == x) {
+                claimers.remove(i);
+            }
+        }
+        let mut next = claimers.clone();
+        next.sort_unstable();
+        next.dedup();
+        if cur.as_ref().map(|c| c.1 == next) == Some(true) { continue; }
+        if let Some((start, prev)) = cur.take() {
+            if !prev.is_empty() { tree.insert_segment(Segment::new(start, pos), prev).unwrap(); }
+        }
+        cur = Some((pos, next));
+    }
+    tree
+}
+
+/// The disk regions claimed by fileobjects, split into disjoint regions each claimed by the same
+/// fileobjects throughout.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    tree: SegmentTree<u64, Vec<usize>>,
+    files: Vec<Origin>,
+}
+
+impl Coverage {
+    /// Builds the map from the reports, given with their names.
+    pub fn new<R, I>(reports: R) -> Self
+        where R: IntoIterator<Item=(String, I)>, I: IntoIterator<Item=(String, FileDescription)>
+    {
+        let mut files = Vec::new();
+        let mut events = Vec::new();
+        for (report, descs) in reports {
+            for (filename, desc) in descs {
+                let idx = files.len();
+                files.push(Origin { report: report.clone(), filename });
+                events.extend(desc.as_ref().iter().map(|&br| (br, idx)));
+            }
+        }
+        Coverage { tree: sweep(events), files }
+    }
+
+    /// The claimed regions in order, each with the fileobjects claiming it.
+    pub fn regions(&self) -> impl Iterator<Item=(Segment<u64>, Vec<&Origin>)> + '_ {
+        self.tree.iter().map(move |(seg, idxs)| (seg, idxs.iter().map(|&i| &self.files[i]).collect()))
+    }
+
+    /// The regions claimed by more than one fileobject, in order.
+    pub fn multiply_claimed(&self) -> impl Iterator<Item=(Segment<u64>, Vec<&Origin>)> + '_ {
+        self.regions().filter(|(_, files)| files.len() > 1)
+    }
+
+    /// The number of bytes claimed by any fileobject.
+    pub fn claimed_bytes(&self) -> u64 {
+        self.tree.iter().map(|(seg, _)| seg.end - seg.start).sum()
+    }
+
+    /// The number of bytes claimed by more than one fileobject.
+    pub fn multiply_claimed_bytes(&self) -> u64 {
+        self.tree.iter().filter(|(_, idxs)| idxs.len() > 1).map(|(seg, _)| seg.end - seg.start).sum()
+    }
+
+    /// The regions within the given range (usually the volume of the report) not claimed by any
+    /// fileobject, in order.
+    pub fn gaps(&self, within: &Segment<u64>) -> Vec<Segment<u64>> {
+        let mut gaps = Vec::new();
+        let mut pos = within.start;
+        for (seg, _) in self.tree.iter_intersecting(within) {
+            if seg.start > pos { gaps.push(Segment::new(pos, seg.start)); }
+            pos = pos.max(seg.end);
+        }
+        if pos < within.end { gaps.push(Segment::new(pos, within.end)); }
+        gaps
+    }
+
+    /// Like `gaps`, but each gap is shrunk to whole sectors, and only those of at least
+    /// `min_size` bytes are kept.
+    pub fn aligned_gaps(&self, within: &Segment<u64>, sectorsize: u64, min_size: u64) -> Vec<Segment<u64>> {
+        self.gaps(within).into_iter().filter_map(|seg| {
+            let start = div_ceil(seg.start, sectorsize) * sectorsize;
+            let end = seg.end / sectorsize * sectorsize;
+            if start < end && end - start >= min_size { Some(Segment::new(start, end)) } else { None }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use crate::test_util::fd;
+    use segment_tree::Segment;
+
+    #[test]
+    fn test_coverage() {
+        let reports = vec![
+            ("r0".to_owned(), vec![("a".to_owned(), fd(&[(100, 50), (150, 50)])), ("b".to_owned(), fd(&[(300, 100)]))]),
+            ("r1".to_owned(), vec![("c".to_owned(), fd(&[(350, 100)]))]),
+        ];
+        let coverage = Coverage::new(reports);
+        let regions: Vec<_> = coverage.regions()
+            .map(|(seg, files)| (seg, files.into_iter().map(|o| o.filename.as_str()).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(regions, vec![
+            (Segment::new(100, 200), vec!["a"]),
+            (Segment::new(300, 350), vec!["b"]),
+            (Segment::new(350, 400), vec!["b", "c"]),
+            (Segment::new(400, 450), vec!["c"]),
+        ]);
+        assert_eq!(coverage.claimed_bytes(), 250);
+        assert_eq!(coverage.multiply_claimed_bytes(), 50);
+        assert_eq!(coverage.multiply_claimed().map(|(seg, files)| (seg, files[1].report.clone())).collect::<Vec<_>>(),
+                   vec![(Segment::new(350, 400), "r1".to_owned())]);
+        assert_eq!(coverage.gaps(&Segment::new(0, 1000)), vec![Segment::new(0, 100), Segment::new(200, 300), Segment::new(450, 1000)]);
+        assert_eq!(coverage.gaps(&Segment::new(120, 320)), vec![Segment::new(200, 300)]);
+        assert_eq!(coverage.gaps(&Segment::new(120, 180)), vec![]);
+        assert_eq!(coverage.aligned_gaps(&Segment::new(0, 1000), 64, 0), vec![Segment::new(0, 64), Segment::new(512, 960)]);
+        assert_eq!(coverage.aligned_gaps(&Segment::new(0, 1000), 64, 65), vec![Segment::new(512, 960)]);
+    }
+}
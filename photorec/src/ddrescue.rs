@@ -0,0 +1,320 @@
+//
+// GNU ddrescue mapfiles: a list of contiguous blocks of the input, each with
+// the status of its rescue.
+//
+use std::io::{self, BufRead, Write};
+use std::fmt;
+
+use thiserror::Error;
+
+use segment_tree::Segment;
+
+use super::coverage::sweep;
+use super::file_description::FileDescription;
+
+/// The status of a block in a mapfile, as the character ddrescue uses for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlockStatus {
+    NonTried,
+    NonTrimmed,
+    NonScraped,
+    BadSector,
+    Finished,
+}
+
+impl BlockStatus {
+    pub fn as_char(&self) -> char {
+        match self {
+            BlockStatus::NonTried => '?',
+            BlockStatus::NonTrimmed => '*',
+            BlockStatus::NonScraped => '/',
+            BlockStatus::BadSector => '-',
+            BlockStatus::Finished => '+',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '?' => Some(BlockStatus::NonTried),
+            '*' => Some(BlockStatus::NonTrimmed),
+            '/' => Some(BlockStatus::NonScraped),
+            '-' => Some(BlockStatus::BadSector),
+            '+' => Some(BlockStatus::Finished),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BlockStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_char())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub pos: u64,
+    pub size: u64,
+    pub status: BlockStatus,
+}
+
+#[derive(Error, Debug)]
+pub enum MapfileError {
+    #[error("Error reading: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed mapfile at line {line}: {text}")]
+    Malformed { line: usize, text: String },
+    #[error("Missing status line in mapfile")]
+    MissingStatus,
+}
+
+/// How many bytes of a file lie in blocks of each status. Bytes beyond the blocks of the mapfile
+/// count as non-tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RescueStatus {
+    pub finished: u64,
+    pub non_tried: u64,
+    pub non_trimmed: u64,
+    pub non_scraped: u64,
+    pub bad_sector: u64,
+}
+
+impl RescueStatus {
+    pub fn get(&self, status: BlockStatus) -> u64 {
+        match status {
+            BlockStatus::NonTried => self.non_tried,
+            BlockStatus::NonTrimmed => self.non_trimmed,
+            BlockStatus::NonScraped => self.non_scraped,
+            BlockStatus::BadSector => self.bad_sector,
+            BlockStatus::Finished => self.finished,
+        }
+    }
+
+    fn add(&mut self, status: BlockStatus, n: u64) {
+        *match status {
+            BlockStatus::NonTried => &mut self.non_tried,
+            BlockStatus::NonTrimmed => &mut self.non_trimmed,
+            BlockStatus::NonScraped => &mut self.non_scraped,
+            BlockStatus::BadSector => &mut self.bad_sector,
+            BlockStatus::Finished => &mut self.finished,
+        } += n;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.finished + self.non_tried + self.non_trimmed + self.non_scraped + self.bad_sector
+    }
+
+    /// The fraction of the bytes in blocks of the given status; 0 for an empty file.
+    pub fn fraction(&self, status: BlockStatus) -> f64 {
+        if self.total() == 0 { 0.0 } else { self.get(status) as f64 / self.total() as f64 }
+    }
+
+    /// Whether all the bytes were rescued.
+    pub fn is_complete(&self) -> bool { self.finished == self.total() }
+}
+
+impl std::ops::AddAssign for RescueStatus {
+    fn add_assign(&mut self, other: Self) {
+        self.finished += other.finished;
+        self.non_tried += other.non_tried;
+        self.non_trimmed += other.non_trimmed;
+        self.non_scraped += other.non_scraped;
+        self.bad_sector += other.bad_sector;
+    }
+}
+
+/// Parses a number the way ddrescue writes them: hex starting with `0x`, or decimal.
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapfile {
+    pub current_pos: u64,
+    pub current_status: char,
+    pub blocks: Vec<Block>,
+}
+
+impl Mapfile {
+    /// A mapfile for use as a domain (`--domain-mapfile`), covering `0..size`, in which exactly
+    /// the given segments are finished. The segments must be sorted and disjoint.
+    pub fn domain<I: IntoIterator<Item=Segment<u64>>>(segments: I, size: u64) -> Self {
+        let mut blocks = Vec::new();
+        let mut pos = 0;
+        let mut push = |start: u64, end: u64, status| {
+            if start < end { blocks.push(Block { pos: start, size: end - start, status }); }
+        };
+        for seg in segments {
+            push(pos, seg.start.min(size), BlockStatus::NonTried);
+            push(seg.start.max(pos), seg.end.min(size), BlockStatus::Finished);
+            pos = pos.max(seg.end);
+        }
+        push(pos, size, BlockStatus::NonTried);
+        Mapfile { current_pos: 0, current_status: '+', blocks }
+    }
+
+    /// A domain mapfile covering `0..size`, in which exactly the disk ranges used by the given
+    /// files are finished, with adjacent and overlapping ranges coalesced.
+    pub fn domain_of<'a, I: IntoIterator<Item=&'a FileDescription>>(descs: I, size: u64) -> Self {
+        let runs = descs.into_iter().flat_map(|d| d.as_ref().iter().map(|&br| (br, ()))).collect();
+        Self::domain(sweep(runs).iter().map(|(seg, _)| seg), size)
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> Result<Self, MapfileError> {
+        let mut status_line: Option<(u64, char)> = None;
+        let mut blocks = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let malformed = || MapfileError::Malformed { line: i + 1, text: line.to_owned() };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let single_char = |s: &str| { let mut it = s.chars(); it.next().filter(|_| it.next().is_none()) };
+            if status_line.is_none() {
+                // The current_pass field is missing in older mapfiles
+                if fields.len() < 2 { return Err(malformed()); }
+                let pos = parse_number(fields[0]).ok_or_else(malformed)?;
+                status_line = Some((pos, single_char(fields[1]).ok_or_else(malformed)?));
+            } else {
+                if fields.len() != 3 { return Err(malformed()); }
+                blocks.push(Block {
+                    pos: parse_number(fields[0]).ok_or_else(malformed)?,
+                    size: parse_number(fields[1]).ok_or_else(malformed)?,
+                    status: single_char(fields[2]).and_then(BlockStatus::from_char).ok_or_else(malformed)?,
+                });
+            }
+        }
+        let (current_pos, current_status) = status_line.ok_or(MapfileError::MissingStatus)?;
+        blocks.sort_unstable_by_key(|b| b.pos);
+        Ok(Mapfile { current_pos, current_status, blocks })
+    }
+
+    /// How many bytes of the file lie in blocks of each status.
+    pub fn rescue_status(&self, desc: &FileDescription) -> RescueStatus {
+        let mut ret = RescueStatus::default();
+        for br in desc.as_ref() {
+            let (mut pos, end) = (br.disk_pos, br.disk_pos + br.len);
+            let mut idx = self.blocks.partition_point(|b| b.pos + b.size <= pos);
+            while pos < end {
+                let (next, status) = match self.blocks.get(idx) {
+                    Some(b) if b.pos <= pos => (b.pos + b.size, b.status),
+                    Some(b) => (b.pos, BlockStatus::NonTried),
+                    None => (end, BlockStatus::NonTried),
+                };
+                let next = next.min(end);
+                ret.add(status, next - pos);
+                if self.blocks.get(idx).is_some_and(|b| b.pos <= pos) { idx += 1; }
+                pos = next;
+            }
+        }
+        ret
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "# Mapfile. Created by photorec")?;
+        writeln!(writer, "# current_pos  current_status  current_pass")?;
+        writeln!(writer, "{:#010x}     {}               1", self.current_pos, self.current_status)?;
+        writeln!(writer, "#      pos        size  status")?;
+        for b in &self.blocks {
+            writeln!(writer, "{:#010x}  {:#010x}  {}", b.pos, b.size, b.status)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mapfile, MapfileError, Block, BlockStatus, RescueStatus};
+    use crate::file_description::{ByteRun, FileDescription};
+    use segment_tree::Segment;
+
+    #[test]
+    fn test_domain_write() {
+        let mapfile = Mapfile::domain(vec![Segment::new(0, 0x200), Segment::new(0x400, 0x1000)], 0x2000);
+        assert_eq!(mapfile.blocks, vec![
+            Block { pos: 0, size: 0x200, status: BlockStatus::Finished },
+            Block { pos: 0x200, size: 0x200, status: BlockStatus::NonTried },
+            Block { pos: 0x400, size: 0xc00, status: BlockStatus::Finished },
+            Block { pos: 0x1000, size: 0x1000, status: BlockStatus::NonTried },
+        ]);
+        let mut out = Vec::new();
+        mapfile.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+# Mapfile. Created by photorec
+# current_pos  current_status  current_pass
+0x00000000     +               1
+#      pos        size  status
+0x00000000  0x00000200  +
+0x00000200  0x00000200  ?
+0x00000400  0x00000c00  +
+0x00001000  0x00001000  ?
+");
+    }
+
+    #[test]
+    fn test_domain_of() {
+        let fd = |runs: &[(u64, u64, u64)]| {
+            let size = runs.iter().map(|r| r.2).sum();
+            FileDescription::new(size, runs.iter().map(|&(file_offset, disk_pos, len)| ByteRun { file_offset, disk_pos, len })).unwrap()
+        };
+        let descs = vec![fd(&[(0, 100, 50), (50, 300, 50)]), fd(&[(0, 150, 50)]), fd(&[(0, 320, 100)])];
+        assert_eq!(Mapfile::domain_of(&descs, 1000), Mapfile::domain(vec![Segment::new(100, 200), Segment::new(300, 420)], 1000));
+    }
+
+    #[test]
+    fn test_parse() {
+        let s = "\
+# Mapfile. Created by GNU ddrescue version 1.25
+# Command line: ddrescue /dev/sdb image.img image.map
+# current_pos  current_status  current_pass
+0x00001000     ?               1
+#      pos        size  status
+0x00000000  0x00001000  +
+0x00001000  0x00000200  -
+0x00001200  0x00000E00  /
+8192\t  0x1000  ?
+";
+        let mapfile = Mapfile::parse(s.as_bytes()).unwrap();
+        assert_eq!((mapfile.current_pos, mapfile.current_status), (0x1000, '?'));
+        assert_eq!(mapfile.blocks, vec![
+            Block { pos: 0, size: 0x1000, status: BlockStatus::Finished },
+            Block { pos: 0x1000, size: 0x200, status: BlockStatus::BadSector },
+            Block { pos: 0x1200, size: 0xe00, status: BlockStatus::NonScraped },
+            Block { pos: 0x2000, size: 0x1000, status: BlockStatus::NonTried },
+        ]);
+        assert_let!(Err(MapfileError::Malformed { line: 2, .. }) = Mapfile::parse("0 +\n0 x +\n".as_bytes()));
+        assert_let!(Err(MapfileError::Malformed { line: 2, .. }) = Mapfile::parse("0 +\n0 10 x\n".as_bytes()));
+        assert_let!(Err(MapfileError::MissingStatus) = Mapfile::parse("# nothing\n".as_bytes()));
+
+        let mut out = Vec::new();
+        Mapfile::domain(vec![Segment::new(0x200, 0x400)], 0x1000).write(&mut out).unwrap();
+        assert_eq!(Mapfile::parse(out.as_slice()).unwrap(), Mapfile::domain(vec![Segment::new(0x200, 0x400)], 0x1000));
+    }
+
+    #[test]
+    fn test_rescue_status() {
+        let mapfile = Mapfile {
+            current_pos: 0,
+            current_status: '+',
+            blocks: vec![
+                Block { pos: 0, size: 100, status: BlockStatus::Finished },
+                Block { pos: 100, size: 10, status: BlockStatus::BadSector },
+                Block { pos: 110, size: 90, status: BlockStatus::Finished },
+                Block { pos: 200, size: 100, status: BlockStatus::NonTried },
+            ],
+        };
+        let desc = FileDescription::new(150, vec![
+            ByteRun { file_offset: 0, disk_pos: 50, len: 100 },
+            ByteRun { file_offset: 100, disk_pos: 280, len: 50 },
+        ]).unwrap();
+        let status = mapfile.rescue_status(&desc);
+        assert_eq!(status, RescueStatus { finished: 90, non_tried: 50, bad_sector: 10, ..Default::default() });
+        assert!(!status.is_complete());
+        assert_eq!(status.fraction(BlockStatus::Finished), 0.6);
+        let desc = FileDescription::new(20, vec![ByteRun { file_offset: 0, disk_pos: 120, len: 20 }]).unwrap();
+        assert!(mapfile.rescue_status(&desc).is_complete());
+    }
+}
@@ -0,0 +1,197 @@
+//
+// Comparison of two reports, classifying each fileobject by how it relates to
+// the fileobjects of the other report sharing disk runs with it.
+//
+use std::cmp::Ordering;
+use std::fmt;
+
+use segment_tree::{SegmentArrayTree, SegmentArrayTreeError, AddStatus, Compatibility, Exact};
+
+use super::file_description::{ByteRun, FileDescription};
+use super::overlap::OverlapIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiffKind {
+    /// Both reports describe the file with the same byte runs
+    Identical,
+    /// Only the first report has a file using these disk runs
+    OnlyInA,
+    /// Only the second report has a file using these disk runs
+    OnlyInB,
+    /// The description in the first report is a strict extension of the one in the second
+    ExtendedInA,
+    /// The description in the second report is a strict extension of the one in the first
+    ExtendedInB,
+    /// The descriptions share disk runs, but neither is an extension of the other
+    Conflicting,
+}
+
+impl DiffKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffKind::Identical => "identical",
+            DiffKind::OnlyInA => "only_in_a",
+            DiffKind::OnlyInB => "only_in_b",
+            DiffKind::ExtendedInA => "extended_in_a",
+            DiffKind::ExtendedInB => "extended_in_b",
+            DiffKind::Conflicting => "conflicting",
+        }
+    }
+}
+
+impl fmt::Display for DiffKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single line of the diff: a file of one report, the file of the other report it relates to
+/// (if any), and how.
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    pub kind: DiffKind,
+    pub a: Option<(String, FileDescription)>,
+    pub b: Option<(String, FileDescription)>,
+}
+
+/// Adds `a` and then `b` to an empty SegmentArrayTree using the given compatibility rule,
+/// returning the result of adding `b`.
+pub(crate) fn add_pair<'a, C>(a: &'a FileDescription, b: &'a FileDescription, rule: C) -> Result<AddStatus<&'a FileDescription>, SegmentArrayTreeError>
+    where C: Compatibility<ByteRun>
+{
+    let mut sat = SegmentArrayTree::with_compatibility(rule);
+    sat.add(a).map_err(|(_, e)| e)?;
+    sat.add(b).map_err(|(_, e)| e)
+}
+
+/// Decides how two file descriptions relate, the same way a SegmentArrayTree would when adding
+/// `b` after `a`. Returns None if they don't share any disk runs.
+pub fn relate(a: &FileDescription, b: &FileDescription) -> Option<DiffKind> {
+    relate_with(a, b, Exact)
+}
+
+/// Like `relate`, but with the given compatibility rule deciding which description extends the
+/// other.
+pub fn relate_with<C>(a: &FileDescription, b: &FileDescription, rule: C) -> Option<DiffKind>
+    where C: Compatibility<ByteRun> + Copy
+{
+    match add_pair(a, b, rule) {
+        Ok(AddStatus::Added) => None,
+        Ok(AddStatus::AlreadyContained(b)) => {
+            if rule.compare(b.as_ref(), a.as_ref()) == Some(Ordering::Equal) { Some(DiffKind::Identical) } else { Some(DiffKind::ExtendedInA) }
+        }
+        Ok(AddStatus::Replaced(_)) => Some(DiffKind::ExtendedInB),
+        Err(_) => Some(DiffKind::Conflicting),
+    }
+}
+
+/// Compares the fileobjects of two reports. Every fileobject of `b` gets an entry for each
+/// fileobject of `a` it shares disk runs with, or an OnlyInB entry if there are none; these are
+/// followed by OnlyInA entries for the fileobjects of `a` not matched by any of `b`.
+pub fn diff<A, B>(a: A, b: B) -> Vec<DiffEntry>
+    where A: IntoIterator<Item=(String, FileDescription)>, B: IntoIterator<Item=(String, FileDescription)>
+{
+    let a: Vec<(String, FileDescription)> = a.into_iter().collect();
+    let index = OverlapIndex::new(a.iter().map(|x| &x.1));
+
+    let mut matched = vec![false; a.len()];
+    let mut entries = Vec::new();
+    for (name, desc) in b {
+        let mut found = false;
+        for idx in index.intersecting(&desc) {
+            if let Some(kind) = relate(&a[idx].1, &desc) {
+                matched[idx] = true;
+                found = true;
+                entries.push(DiffEntry { kind, a: Some(a[idx].clone()), b: Some((name.clone(), desc.clone())) });
+            }
+        }
+        if !found {
+            entries.push(DiffEntry { kind: DiffKind::OnlyInB, a: None, b: Some((name, desc)) });
+        }
+    }
+    entries.extend(a.into_iter().zip(matched).filter(|(_, m)| !m).map(|(x, _)| {
+        DiffEntry { kind: DiffKind::OnlyInA, a: Some(x), b: None }
+    }));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, relate, relate_with, DiffKind};
+    use crate::file_description::TrimmedLastRun;
+    use crate::test_util::fd;
+
+    #[test]
+    fn test_relate() {
+        let a = fd(&[(100, 10), (200, 10)]);
+        assert_eq!(relate(&a, &fd(&[(100, 10), (200, 10)])), Some(DiffKind::Identical));
+        assert_eq!(relate(&a, &fd(&[(100, 10)])), Some(DiffKind::ExtendedInA));
+        assert_eq!(relate(&a, &fd(&[(100, 10), (200, 10), (300, 10)])), Some(DiffKind::ExtendedInB));
+        assert_eq!(relate(&a, &fd(&[(100, 10), (250, 10)])), Some(DiffKind::Conflicting));
+        assert_eq!(relate(&a, &fd(&[(105, 10)])), Some(DiffKind::Conflicting));
+        assert_eq!(relate(&a, &fd(&[(500, 10)])), None);
+    }
+
+    #[test]
+    fn test_relate_trimmed_last_run() {
+        let a = fd(&[(100, 10), (200, 10)]);
+        assert_eq!(relate(&a, &fd(&[(100, 10), (200, 5)])), Some(DiffKind::Conflicting));
+        assert_eq!(relate_with(&a, &fd(&[(100, 10), (200, 10)]), TrimmedLastRun), Some(DiffKind::Identical));
+        assert_eq!(relate_with(&a, &fd(&[(100, 10), (200, 5)]), TrimmedLastRun), Some(DiffKind::ExtendedInA));
+        assert_eq!(relate_with(&a, &fd(&[(100, 5)]), TrimmedLastRun), Some(DiffKind::ExtendedInA));
+        assert_eq!(relate_with(&a, &fd(&[(100, 10), (200, 20)]), TrimmedLastRun), Some(DiffKind::ExtendedInB));
+        assert_eq!(relate_with(&a, &fd(&[(100, 10), (200, 20), (300, 10)]), TrimmedLastRun), Some(DiffKind::ExtendedInB));
+        // Only the last run of the shorter description may be the trimmed one
+        assert_eq!(relate_with(&a, &fd(&[(100, 5), (300, 10)]), TrimmedLastRun), Some(DiffKind::Conflicting));
+        assert_eq!(relate_with(&a, &fd(&[(100, 10), (205, 5)]), TrimmedLastRun), Some(DiffKind::Conflicting));
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = vec![
+            ("same".to_owned(), fd(&[(100, 10), (200, 10)])),
+            ("short".to_owned(), fd(&[(300, 10)])),
+            ("long".to_owned(), fd(&[(400, 10), (500, 10)])),
+            ("conflict".to_owned(), fd(&[(600, 10)])),
+            ("a_only".to_owned(), fd(&[(700, 10)])),
+        ];
+        let b = vec![
+            ("b_only".to_owned(), fd(&[(800, 10)])),
+            ("long".to_owned(), fd(&[(400, 10)])),
+            ("short".to_owned(), fd(&[(300, 10), (310, 10)])),
+            ("conflict".to_owned(), fd(&[(605, 10)])),
+            ("same".to_owned(), fd(&[(100, 10), (200, 10)])),
+        ];
+        let entries = diff(a, b);
+        let summary: Vec<_> = entries.iter().map(|e| {
+            (e.kind, e.a.as_ref().map(|x| x.0.as_str()), e.b.as_ref().map(|x| x.0.as_str()))
+        }).collect();
+        assert_eq!(summary, vec![
+            (DiffKind::OnlyInB, None, Some("b_only")),
+            (DiffKind::ExtendedInA, Some("long"), Some("long")),
+            (DiffKind::ExtendedInB, Some("short"), Some("short")),
+            (DiffKind::Conflicting, Some("conflict"), Some("conflict")),
+            (DiffKind::Identical, Some("same"), Some("same")),
+            (DiffKind::OnlyInA, Some("a_only"), None),
+        ]);
+    }
+
+    #[test]
+    fn test_diff_overlapping_within_report() {
+        let a = vec![
+            ("x".to_owned(), fd(&[(100, 10)])),
+            ("y".to_owned(), fd(&[(100, 10), (200, 10)])),
+        ];
+        let b = vec![
+            ("z".to_owned(), fd(&[(100, 10), (200, 10)])),
+        ];
+        let entries = diff(a, b);
+        let summary: Vec<_> = entries.iter().map(|e| {
+            (e.kind, e.a.as_ref().map(|x| x.0.as_str()), e.b.as_ref().map(|x| x.0.as_str()))
+        }).collect();
+        assert_eq!(summary, vec![
+            (DiffKind::ExtendedInB, Some("x"), Some("z")),
+            (DiffKind::Identical, Some("y"), Some("z")),
+        ]);
+    }
+}
@@ -0,0 +1,72 @@
+//
+// A reverse index from disk positions to the fileobjects containing them, and
+// the offsets within those files.
+//
+use segment_tree::SegmentTree;
+
+use super::coverage::sweep;
+use super::file_description::{ByteRun, FileDescription};
+use super::report::Origin;
+
+/// A fileobject containing some disk position, and where in the file it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit<'a> {
+    pub file: &'a Origin,
+    pub file_offset: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskIndex {
+    tree: SegmentTree<u64, Vec<(usize, ByteRun)>>,
+    files: Vec<Origin>,
+}
+
+impl DiskIndex {
+    /// Builds the index from the reports, given with their names.
+    pub fn new<R, I>(reports: R) -> Self
+        where R: IntoIterator<Item=(String, I)>, I: IntoIterator<Item=(String, FileDescription)>
+    {
+        let mut files = Vec::new();
+        let mut runs = Vec::new();
+        for (report, descs) in reports {
+            for (filename, desc) in descs {
+                let idx = files.len();
+                files.push(Origin { report: report.clone(), filename });
+                runs.extend(desc.as_ref().iter().map(|&br| (br, (idx, br))));
+            }
+        }
+        DiskIndex { tree: sweep(runs), files }
+    }
+
+    /// The fileobjects containing the byte at the given disk position, in the order of the reports
+    /// and of the fileobjects within them.
+    pub fn lookup(&self, pos: u64) -> Vec<Hit<'_>> {
+        self.tree.get_containing_segment(&pos).map_or_else(Vec::new, |(_, claims)| {
+            claims.iter().map(|(idx, br)| Hit { file: &self.files[*idx], file_offset: br.file_offset + (pos - br.disk_pos) }).collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiskIndex;
+    use crate::test_util::fd;
+
+    #[test]
+    fn test_lookup() {
+        let reports = vec![
+            ("r0".to_owned(), vec![("a".to_owned(), fd(&[(100, 50), (300, 50)]))]),
+            ("r1".to_owned(), vec![("b".to_owned(), fd(&[(320, 100)]))]),
+        ];
+        let index = DiskIndex::new(reports);
+        let lookup = |pos| index.lookup(pos).into_iter().map(|h| (h.file.filename.as_str(), h.file_offset)).collect::<Vec<_>>();
+        assert_eq!(lookup(100), vec![("a", 0)]);
+        assert_eq!(lookup(149), vec![("a", 49)]);
+        assert_eq!(lookup(150), vec![]);
+        assert_eq!(lookup(99), vec![]);
+        assert_eq!(lookup(310), vec![("a", 60)]);
+        assert_eq!(lookup(330), vec![("a", 80), ("b", 10)]);
+        assert_eq!(lookup(350), vec![("b", 30)]);
+        assert_eq!(lookup(1000), vec![]);
+    }
+}
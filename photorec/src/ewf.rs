@@ -0,0 +1,434 @@
+//
+// Expert Witness Format (EWF-E01, as written by EnCase and ewfacquire) images:
+// a set of segment files (image.E01, image.E02, ...) holding the media in
+// chunks, each stored plain or zlib-compressed, and located by tables. Segments
+// of the later EWF2 (Ex01) format are recognised, but refused.
+//
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+use thiserror::Error;
+
+use super::num::div_ceil;
+
+const SIGNATURE: &[u8; 8] = b"EVF\x09\x0d\x0a\xff\x00";
+const EWF2_SIGNATURE: &[u8; 8] = b"EVF2\x0d\x0a\x81\x00";
+const FILE_HEADER_SIZE: u64 = 13;
+const DESCRIPTOR_SIZE: u64 = 76;
+/// The size of the data of the volume section of SMART images, whose sector count is 32 bits.
+const SMART_VOLUME_SIZE: u64 = 94;
+
+#[derive(Error, Debug)]
+pub enum EwfError {
+    #[error("Error reading: {0}")]
+    Io(#[from] io::Error),
+    #[error("Segment {0} is not an EWF file")]
+    BadSignature(usize),
+    #[error("Segment {0} is in the EWF2 (Ex01) format, which is not supported")]
+    Ewf2(usize),
+    #[error("Malformed EWF image: {0}")]
+    Malformed(&'static str),
+}
+
+/// Where a chunk is stored. `end` is only an upper bound on the end of the data, which is enough
+/// for compressed chunks.
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    segment: usize,
+    offset: u64,
+    end: u64,
+    compressed: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    chunk_size: u64,
+    size: u64,
+}
+
+/// An EWF image, read (impl Read + Seek) as the media it holds. Decompressed chunks are kept in a
+/// cache of the most recently used ones. Can be used as the inner reader of a ByteRunsReader.
+pub struct EwfImage<R> {
+    segments: Vec<R>,
+    chunks: Vec<Chunk>,
+    chunk_size: u64,
+    size: u64,
+    /// Most recently used first
+    cache: VecDeque<(usize, Vec<u8>)>,
+    cache_size: usize,
+    pos: u64,
+}
+
+/// The extension of the segment after one with extension `ext`, keeping its first letter and case:
+/// E01 .. E99, then EAA .. EZZ, then FAA and so on.
+fn next_extension(ext: &str) -> Option<String> {
+    let b = ext.as_bytes();
+    if b.len() != 3 || !b[0].is_ascii_alphabetic() { return None; }
+    let a = if b[0].is_ascii_uppercase() { b'A' } else { b'a' };
+    if b[1].is_ascii_digit() && b[2].is_ascii_digit() {
+        let n = ext[1..].parse::<u32>().ok()? + 1;
+        return Some(if n <= 99 {
+            format!("{}{:02}", b[0] as char, n)
+        } else {
+            String::from_utf8(vec![b[0], a, a]).ok()?
+        });
+    }
+    let (x, y) = (b[1].checked_sub(a)?, b[2].checked_sub(a)?);
+    if x >= 26 || y >= 26 { return None; }
+    let n = x as u32 * 26 + y as u32 + 1;
+    if n < 26 * 26 {
+        String::from_utf8(vec![b[0], a + (n / 26) as u8, a + (n % 26) as u8]).ok()
+    } else if b[0] == a + 25 {
+        None
+    } else {
+        String::from_utf8(vec![b[0] + 1, a, a]).ok()
+    }
+}
+
+/// The paths of the segments of the image starting at `first`, i.e. `first` and the following
+/// segments which exist.
+pub fn ewf_segment_paths(first: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![first.to_path_buf()];
+    loop {
+        let next = paths.last().unwrap().extension().and_then(OsStr::to_str)
+            .and_then(next_extension)
+            .map(|ext| first.with_extension(ext));
+        match next {
+            Some(p) if p.exists() => paths.push(p),
+            _ => break,
+        }
+    }
+    paths
+}
+
+/// Whether the file starts like a segment of an EWF image (of either version).
+pub fn is_ewf<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let mut signature = [0; 8];
+    reader.seek(SeekFrom::Start(0))?;
+    match reader.read_exact(&mut signature) {
+        Ok(()) => Ok(&signature == SIGNATURE || &signature == EWF2_SIGNATURE),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    let mut x = [0; 4];
+    x.copy_from_slice(&buf[at..at + 4]);
+    u32::from_le_bytes(x)
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    let mut x = [0; 8];
+    x.copy_from_slice(&buf[at..at + 8]);
+    u64::from_le_bytes(x)
+}
+
+/// Reads the sections of a segment, adding the chunks of its tables, and finding the geometry in
+/// its volume section if it has one.
+fn parse_segment<R: Read + Seek>(seg: &mut R, idx: usize, chunks: &mut Vec<Chunk>, geometry: &mut Option<Geometry>)
+    -> Result<(), EwfError>
+{
+    let mut header = [0; FILE_HEADER_SIZE as usize];
+    seg.seek(SeekFrom::Start(0))?;
+    seg.read_exact(&mut header)?;
+    if &header[..8] == EWF2_SIGNATURE { return Err(EwfError::Ewf2(idx)); }
+    if &header[..8] != SIGNATURE { return Err(EwfError::BadSignature(idx)); }
+    let mut offset = FILE_HEADER_SIZE;
+    loop {
+        let mut desc = [0; DESCRIPTOR_SIZE as usize];
+        seg.seek(SeekFrom::Start(offset))?;
+        seg.read_exact(&mut desc)?;
+        let kind = desc[..16].split(|&b| b == 0).next().unwrap();
+        let (next, size) = (u64_at(&desc, 16), u64_at(&desc, 24));
+        match kind {
+            b"volume" | b"disk" | b"data" if geometry.is_none() => {
+                let mut data = [0; 24];
+                seg.read_exact(&mut data)?;
+                let sector_count = if size.saturating_sub(DESCRIPTOR_SIZE) == SMART_VOLUME_SIZE {
+                    u32_at(&data, 16) as u64
+                } else {
+                    u64_at(&data, 16)
+                };
+                let bytes_per_sector = u32_at(&data, 12) as u64;
+                let chunk_size = u32_at(&data, 8) as u64 * bytes_per_sector;
+                if chunk_size == 0 { return Err(EwfError::Malformed("zero chunk size")); }
+                let size = sector_count.checked_mul(bytes_per_sector).ok_or(EwfError::Malformed("media too large"))?;
+                *geometry = Some(Geometry { chunk_size, size });
+            },
+            b"table" => {
+                let mut table_header = [0; 24];
+                seg.read_exact(&mut table_header)?;
+                let count = u32_at(&table_header, 0) as u64;
+                let base = u64_at(&table_header, 8);
+                // The count isn't trusted to size the buffer beyond what the section holds
+                if count * 4 > size.saturating_sub(DESCRIPTOR_SIZE + 24) {
+                    return Err(EwfError::Malformed("table larger than its section"));
+                }
+                let mut entries = vec![0; count as usize * 4];
+                seg.read_exact(&mut entries)?;
+                let entries = entries.chunks(4).map(|e| u32_at(e, 0)).collect::<Vec<_>>();
+                for (i, &e) in entries.iter().enumerate() {
+                    let chunk_offset = base + (e & 0x7fff_ffff) as u64;
+                    // A chunk ends where the next begins; the last one ends at the table if stored
+                    // before it (in a sectors section), and at the end of the table otherwise
+                    let end = match entries.get(i + 1) {
+                        Some(&e) => base + (e & 0x7fff_ffff) as u64,
+                        None if chunk_offset < offset => offset,
+                        None => offset + size,
+                    };
+                    if end < chunk_offset { return Err(EwfError::Malformed("chunks out of order")); }
+                    chunks.push(Chunk { segment: idx, offset: chunk_offset, end, compressed: e & 0x8000_0000 != 0 });
+                }
+            },
+            b"next" | b"done" => break,
+            _ => {},
+        }
+        if next <= offset { return Err(EwfError::Malformed("sections out of order")); }
+        offset = next;
+    }
+    Ok(())
+}
+
+impl EwfImage<File> {
+    /// Opens the image starting at segment `first`, finding the other segments by their names.
+    pub fn open<P: AsRef<Path>>(first: P) -> Result<Self, EwfError> {
+        let segments = ewf_segment_paths(first.as_ref()).iter().map(File::open).collect::<io::Result<_>>()?;
+        EwfImage::new(segments)
+    }
+}
+
+impl<R: Read + Seek> EwfImage<R> {
+    /// The image made of the segments, in order.
+    pub fn new(mut segments: Vec<R>) -> Result<Self, EwfError> {
+        let mut chunks = Vec::new();
+        let mut geometry = None;
+        for (idx, seg) in segments.iter_mut().enumerate() {
+            parse_segment(seg, idx, &mut chunks, &mut geometry)?;
+        }
+        let Geometry { chunk_size, size } = geometry.ok_or(EwfError::Malformed("no volume section"))?;
+        if (chunks.len() as u64) < div_ceil(size, chunk_size) {
+            return Err(EwfError::Malformed("missing chunks"));
+        }
+        Ok(EwfImage { segments, chunks, chunk_size, size, cache: VecDeque::new(), cache_size: 64, pos: 0 })
+    }
+
+    fn load_chunk(&mut self, idx: usize) -> io::Result<Vec<u8>> {
+        let chunk = self.chunks[idx];
+        let expected = self.chunk_size.min(self.size - idx as u64 * self.chunk_size) as usize;
+        let seg = &mut self.segments[chunk.segment];
+        seg.seek(SeekFrom::Start(chunk.offset))?;
+        let mut data = Vec::with_capacity(self.chunk_size as usize);
+        if chunk.compressed {
+            ZlibDecoder::new(seg.take(chunk.end - chunk.offset)).read_to_end(&mut data)?;
+        } else {
+            // Followed by its checksum, which we don't check
+            seg.take(expected as u64).read_to_end(&mut data)?;
+        }
+        if data.len() < expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("EWF chunk {} is too short", idx)));
+        }
+        data.truncate(expected);
+        Ok(data)
+    }
+
+    fn chunk(&mut self, idx: usize) -> io::Result<&[u8]> {
+        match self.cache.iter().position(|(i, _)| *i == idx) {
+            Some(at) => {
+                let entry = self.cache.remove(at).unwrap();
+                self.cache.push_front(entry);
+            },
+            None => {
+                let data = self.load_chunk(idx)?;
+                if self.cache.len() >= self.cache_size.max(1) { self.cache.pop_back(); }
+                self.cache.push_front((idx, data));
+            },
+        }
+        Ok(&self.cache[0].1)
+    }
+}
+
+impl<R> EwfImage<R> {
+    /// The size of the media.
+    pub fn len(&self) -> u64 { self.size }
+
+    pub fn is_empty(&self) -> bool { self.size == 0 }
+
+    pub fn chunk_size(&self) -> u64 { self.chunk_size }
+
+    /// Sets how many decompressed chunks to keep (at least 1); 64 by default.
+    pub fn set_cache_size(&mut self, cache_size: usize) {
+        self.cache_size = cache_size;
+        self.cache.truncate(cache_size.max(1));
+    }
+}
+
+impl<R: Read + Seek> Read for EwfImage<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut done = 0;
+        while done < buf.len() && self.pos < self.size {
+            let idx = (self.pos / self.chunk_size) as usize;
+            let off = (self.pos % self.chunk_size) as usize;
+            let data = self.chunk(idx)?;
+            let n = (buf.len() - done).min(data.len() - off);
+            buf[done..done + n].copy_from_slice(&data[off..off + n]);
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+}
+
+impl<R> Seek for EwfImage<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x),
+            SeekFrom::End(x) => self.size.checked_add_signed(x),
+        };
+        self.pos = new.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bad seek pos."))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EwfImage, EwfError, next_extension, is_ewf, SIGNATURE, DESCRIPTOR_SIZE};
+    use crate::file_description::{ByteRun, FileDescription, Desc};
+    use crate::reader::ByteRunsReader;
+    use crate::num::div_ceil;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+    fn section(out: &mut Vec<u8>, kind: &str, data: &[u8], last: bool) {
+        let start = out.len() as u64;
+        let size = DESCRIPTOR_SIZE + data.len() as u64;
+        let mut name = [0; 16];
+        name[..kind.len()].copy_from_slice(kind.as_bytes());
+        out.extend_from_slice(&name);
+        out.extend_from_slice(&(if last { start } else { start + size }).to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&[0; 44]);
+        out.extend_from_slice(data);
+    }
+
+    /// A segment with the given chunks, compressed or not, and a volume section if given the
+    /// number of sectors (of 8 bytes, 2 to a chunk).
+    fn segment(number: u16, sectors: Option<u64>, chunks: &[(&[u8], bool)], last: bool) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        out.push(1);
+        out.extend_from_slice(&number.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        if let Some(sectors) = sectors {
+            let mut volume = vec![0; 1052];
+            volume[4..8].copy_from_slice(&(div_ceil(sectors, 2) as u32).to_le_bytes());
+            volume[8..12].copy_from_slice(&2u32.to_le_bytes());
+            volume[12..16].copy_from_slice(&8u32.to_le_bytes());
+            volume[16..24].copy_from_slice(&sectors.to_le_bytes());
+            section(&mut out, "volume", &volume, false);
+        }
+        let data_start = out.len() as u64 + DESCRIPTOR_SIZE;
+        let mut data = Vec::new();
+        let mut entries = Vec::new();
+        for (chunk, compressed) in chunks {
+            let mut entry = data.len() as u32;
+            if *compressed {
+                let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+                enc.write_all(chunk).unwrap();
+                data.extend(enc.finish().unwrap());
+                entry |= 0x8000_0000;
+            } else {
+                data.extend_from_slice(chunk);
+                data.extend_from_slice(&[0; 4]);
+            }
+            entries.push(entry);
+        }
+        section(&mut out, "sectors", &data, false);
+        let mut table = (entries.len() as u32).to_le_bytes().to_vec();
+        table.extend_from_slice(&[0; 4]);
+        table.extend_from_slice(&data_start.to_le_bytes());
+        table.extend_from_slice(&[0; 8]);
+        entries.iter().for_each(|e| table.extend_from_slice(&e.to_le_bytes()));
+        table.extend_from_slice(&[0; 4]);
+        section(&mut out, "table", &table, false);
+        section(&mut out, "table2", &table, false);
+        section(&mut out, if last { "done" } else { "next" }, &[], true);
+        out
+    }
+
+    #[test]
+    fn test_next_extension() {
+        assert_eq!(next_extension("E01").as_deref(), Some("E02"));
+        assert_eq!(next_extension("E99").as_deref(), Some("EAA"));
+        assert_eq!(next_extension("EAZ").as_deref(), Some("EBA"));
+        assert_eq!(next_extension("EZZ").as_deref(), Some("FAA"));
+        assert_eq!(next_extension("e09").as_deref(), Some("e10"));
+        assert_eq!(next_extension("ZZZ"), None);
+        assert_eq!(next_extension("001"), None);
+    }
+
+    #[test]
+    fn test_ewf_image() {
+        // 7 sectors, in chunks of 2 sectors (16 bytes), the last one short
+        let media = (0..56).collect::<Vec<u8>>();
+        let seg1 = segment(1, Some(7), &[(&media[0..16], true), (&media[16..32], false)], false);
+        let seg2 = segment(2, None, &[(&media[32..48], false), (&media[48..56], true)], true);
+        let mut seg1 = Cursor::new(seg1);
+        assert!(is_ewf(&mut seg1).unwrap());
+        assert!(!is_ewf(&mut Cursor::new(media.clone())).unwrap());
+
+        let mut img = EwfImage::new(vec![seg1, Cursor::new(seg2)]).unwrap();
+        img.set_cache_size(2);
+        assert_eq!(img.len(), 56);
+        assert_eq!(img.chunk_size(), 16);
+        let mut out = Vec::new();
+        img.read_to_end(&mut out).unwrap();
+        assert_eq!(out, media);
+        // Crossing chunks, and segments
+        img.seek(SeekFrom::Start(12)).unwrap();
+        let mut buf = [0; 24];
+        img.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &media[12..36]);
+        img.seek(SeekFrom::End(-4)).unwrap();
+        assert_eq!(img.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], &media[52..]);
+
+        let desc = FileDescription::new(10, vec![
+            ByteRun { file_offset: 0, disk_pos: 40, len: 5 },
+            ByteRun { file_offset: 5, disk_pos: 2, len: 5 },
+        ]).unwrap();
+        let mut out = Vec::new();
+        ByteRunsReader::new(&mut img, desc.at_pos(0)).read_to_end(&mut out).unwrap();
+        assert_eq!(out, vec![40, 41, 42, 43, 44, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_ewf_errors() {
+        let res = EwfImage::new(vec![Cursor::new(vec![0; 100])]).err();
+        assert_let!(Some(EwfError::BadSignature(0)) = res);
+        let mut ewf2 = b"EVF2\x0d\x0a\x81\x00".to_vec();
+        ewf2.extend_from_slice(&[0; 100]);
+        let res = EwfImage::new(vec![Cursor::new(ewf2)]).err();
+        assert_let!(Some(EwfError::Ewf2(0)) = res);
+        // Too few chunks for the media
+        let seg = segment(1, Some(8), &[(&[0; 16], false)], true);
+        let res = EwfImage::new(vec![Cursor::new(seg)]).err();
+        assert_let!(Some(EwfError::Malformed(_)) = res);
+        // A table claiming more entries than its section holds
+        let mut seg = segment(1, Some(2), &[(&[0; 16], false)], true);
+        let at = seg.windows(6).position(|x| x == b"table\0").unwrap() + DESCRIPTOR_SIZE as usize;
+        seg[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let res = EwfImage::new(vec![Cursor::new(seg)]).err();
+        assert_let!(Some(EwfError::Malformed("table larger than its section")) = res);
+        // A media size overflowing
+        let mut seg = segment(1, Some(2), &[(&[0; 16], false)], true);
+        let at = seg.windows(7).position(|x| x == b"volume\0").unwrap() + DESCRIPTOR_SIZE as usize + 16;
+        seg[at..at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let res = EwfImage::new(vec![Cursor::new(seg)]).err();
+        assert_let!(Some(EwfError::Malformed("media too large")) = res);
+    }
+}
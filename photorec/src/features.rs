@@ -0,0 +1,101 @@
+//
+// bulk_extractor feature files, and mapping their features onto the recovered
+// files containing them.
+//
+use std::io::{self, BufRead};
+
+use thiserror::Error;
+
+use super::disk_index::{DiskIndex, Hit};
+
+/// A single line of a feature file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    /// The image offset of the feature, or of the container it was found in (e.g. a compressed
+    /// stream) if the path has more components
+    pub offset: u64,
+    /// The forensic path, e.g. `1234` or `1234-GZIP-56`
+    pub path: String,
+    pub feature: String,
+    pub context: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum FeatureError {
+    #[error("Error reading: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed feature at line {line}: {text}")]
+    Malformed { line: usize, text: String },
+}
+
+fn parse_line(line: &str) -> Option<Feature> {
+    let mut fields = line.splitn(3, '\t');
+    let path = fields.next()?;
+    let feature = fields.next()?;
+    let offset = path.split('-').next()?.parse().ok()?;
+    Some(Feature {
+        offset,
+        path: path.to_owned(),
+        feature: feature.to_owned(),
+        context: fields.next().map(|x| x.to_owned()),
+    })
+}
+
+/// Reads the features of a feature file, skipping comments and blank lines.
+pub fn parse_features<R: BufRead>(reader: R) -> impl Iterator<Item=Result<Feature, FeatureError>> {
+    reader.lines().enumerate().filter_map(|(i, line)| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let line = line.trim_start_matches('\u{feff}').trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') { return None; }
+        Some(parse_line(line).ok_or_else(|| FeatureError::Malformed { line: i + 1, text: line.to_owned() }))
+    })
+}
+
+/// Pairs each feature with the fileobjects containing its offset; features in unclaimed space get
+/// no hits.
+pub fn annotate<'a, I>(index: &'a DiskIndex, features: I) -> impl Iterator<Item=(Feature, Vec<Hit<'a>>)> + 'a
+    where I: IntoIterator<Item=Feature>, I::IntoIter: 'a
+{
+    features.into_iter().map(move |f| {
+        let hits = index.lookup(f.offset);
+        (f, hits)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_features, annotate, FeatureError};
+    use crate::disk_index::DiskIndex;
+    use crate::file_description::{ByteRun, FileDescription};
+
+    #[test]
+    fn test_parse_and_annotate() {
+        let s = "\u{feff}# UTF-8 Byte Order Marker; see http://unicode.org/faq/utf_bom.html\n\
+                 # Feature-Recorder: email\n\
+                 1100\talice@example.com\tmail to alice@example.com now\r\n\
+                 \n\
+                 1199-GZIP-34\tbob@example.com\n\
+                 5000\tcarol@example.com\tx\n\
+                 bad line\n";
+        let features: Vec<_> = parse_features(s.as_bytes()).collect();
+        assert_eq!(features.len(), 4);
+        assert_let!(Err(FeatureError::Malformed { line: 7, .. }) = &features[3]);
+        let features: Vec<_> = features.into_iter().filter_map(|f| f.ok()).collect();
+        assert_eq!(features[0].context.as_deref(), Some("mail to alice@example.com now"));
+        assert_eq!((features[1].offset, features[1].path.as_str(), features[1].context.as_deref()), (1199, "1199-GZIP-34", None));
+
+        let desc = FileDescription::new(200, vec![ByteRun { file_offset: 0, disk_pos: 1000, len: 200 }]).unwrap();
+        let index = DiskIndex::new(vec![("r0".to_owned(), vec![("a.eml".to_owned(), desc)])]);
+        let got: Vec<_> = annotate(&index, features).map(|(f, hits)| {
+            (f.feature, hits.into_iter().map(|h| (h.file.filename.clone(), h.file_offset)).collect::<Vec<_>>())
+        }).collect();
+        assert_eq!(got, vec![
+            ("alice@example.com".to_owned(), vec![("a.eml".to_owned(), 100)]),
+            ("bob@example.com".to_owned(), vec![("a.eml".to_owned(), 199)]),
+            ("carol@example.com".to_owned(), vec![]),
+        ]);
+    }
+}
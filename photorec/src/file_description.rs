@@ -6,10 +6,12 @@ use std::io::{Seek, SeekFrom};
 use std::io;
 use std::fmt;
 use std::mem;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
 
 use thiserror::Error;
 
-use segment_tree::Segment;
+use segment_tree::{Segment, Compatibility};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub struct ByteRun {
@@ -30,8 +32,37 @@ impl From<&ByteRun> for Segment<u64> {
     }
 }
 
+/// A compatibility rule for SegmentArrayTree-s of ByteRun-s, under which the descriptions must be
+/// equal except for the last run they share, which may be shorter in the shorter description.
+/// This is what `FileDescription::new_allow_missing` leaves when trimming the last run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimmedLastRun;
+
+impl Compatibility<ByteRun> for TrimmedLastRun {
+    fn compare(&self, new: &[ByteRun], old: &[ByteRun]) -> Option<Ordering> {
+        let by_runs = new.len().cmp(&old.len());
+        let n = new.len().min(old.len());
+        if n == 0 { return Some(by_runs); }
+        let (last_new, last_old) = (&new[n - 1], &old[n - 1]);
+        if new[..n - 1] != old[..n - 1] || (last_new.file_offset, last_new.disk_pos) != (last_old.file_offset, last_old.disk_pos) {
+            return None;
+        }
+        match (by_runs, last_new.len.cmp(&last_old.len)) {
+            // Only the last run of a description may be trimmed
+            (Ordering::Greater, Ordering::Less) | (Ordering::Less, Ordering::Greater) => None,
+            (Ordering::Equal, by_len) => Some(by_len),
+            (by_runs, _) => Some(by_runs),
+        }
+    }
+}
+
 pub trait DescRead {
+    /// The disk range to read from at the current position, up to the end of its ByteRun; of len
+    /// 0 at the end of the file, or in a hole.
     fn desc_read(&mut self) -> ByteRun;
+    /// The hole (a range of the file with no ByteRun) the current position is in, if any.
+    fn desc_hole(&mut self) -> Option<Segment<u64>> { None }
+    /// Advances the position by n bytes, which may not go past the current ByteRun or hole.
     fn adv(&mut self, n: usize);
 }
 
@@ -71,69 +102,262 @@ pub enum FileDescriptionError {
     Missing(u64, u64)
 }
 
-impl FileDescription {
-    pub fn new<T: IntoIterator<Item=ByteRun>>(size: u64, runs: T) -> Result<Self, FileDescriptionError> {
-        let mut runs: Vec<ByteRun> = runs.into_iter().collect();
-        if runs.len() == 0 { return Err(FileDescriptionError::Empty); }
-        runs.sort_unstable();
-        let runs = runs;
+/// What to do with runs in some condition, when building a FileDescription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Fail with the error for the condition
+    Error,
+    /// Repair the runs, recording the repair
+    Fix,
+    /// Repair the runs as by Fix, without recording it
+    Quiet,
+    /// Accept the condition as normal: gaps and a missing tail are left as holes; for the others,
+    /// which a description can't hold, the runs are repaired as by Fix, without recording it
+    Allow,
+}
 
-        let mut off = 0;
-        let mut it = runs.iter();
-        let mut br = it.next().unwrap();
-        // First check the first ByteRun starts at 0
-        if br.file_offset != 0 { return Err(FileDescriptionError::PreGap(*br)); }
-        off += br.len;
-        // Then check all the following ByteRun-s come one after another
-        for br2 in it {
-            if off > size { return Err(FileDescriptionError::Trailing(*br2, size)); }
-            if br2.file_offset > off { return Err(FileDescriptionError::Gap(*br, *br2)); }
-            else if br2.file_offset < off { return Err(FileDescriptionError::Overlap(*br, *br2)); }
-            br = br2;
-            off += br.len;
+/// The action for each condition of the runs of a FileDescriptionBuilder. When runs clash, the
+/// one given first has priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Ranges of the file, before or between runs, which no run stores; fixed by leaving a hole,
+    /// read as zeros
+    pub gaps: Action,
+    /// Runs claiming the same range of the file; fixed by trimming the run of lower priority, or
+    /// splitting it around one nested in it
+    pub overlaps: Action,
+    /// Runs starting past the size; fixed by dropping them. The last run is always trimmed to the
+    /// size, so one starting right at it is kept with no bytes.
+    pub trailing: Action,
+    /// The runs ending before the size; fixed by cutting down the size to where they end
+    pub missing_tail: Action,
+    /// Runs starting at the same offset of the file; fixed by trimming or dropping the run of lower
+    /// priority
+    pub duplicates: Action,
+}
+
+impl Policy {
+    /// Runs must tile the file exactly, as for `FileDescription::new`.
+    pub fn strict() -> Self {
+        Policy { gaps: Action::Error, overlaps: Action::Error, trailing: Action::Error, missing_tail: Action::Error, duplicates: Action::Error }
+    }
+
+    /// As for `FileDescription::new_allow_missing`: files PhotoRec couldn't recover to their end
+    /// are cut down to what was, which is normal for it and so not recorded as a repair.
+    pub fn allow_missing() -> Self {
+        Policy { missing_tail: Action::Quiet, ..Self::strict() }
+    }
+
+    /// As for `FileDescription::new_sparse`: missing ranges are left as holes.
+    pub fn sparse() -> Self {
+        Policy { gaps: Action::Allow, missing_tail: Action::Allow, ..Self::strict() }
+    }
+
+    /// Everything is repaired, if possible, and recorded.
+    pub fn repair() -> Self {
+        Policy { gaps: Action::Fix, overlaps: Action::Fix, trailing: Action::Fix, missing_tail: Action::Fix, duplicates: Action::Fix }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self { Self::strict() }
+}
+
+/// A repair made by a FileDescriptionBuilder to the runs given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// A gap left as a hole, read as zeros
+    ZeroFilled(Segment<u64>),
+    /// A run cut down to the part not overlapping a run of higher priority; one split around a
+    /// nested run is recorded for each part
+    Trimmed { from: ByteRun, to: ByteRun },
+    /// A run past the size, a duplicate of a run of higher priority, or wholly overlapped by one
+    Dropped(ByteRun),
+    /// The size cut down to where the runs end
+    Shrunk { from: u64, to: u64 },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repair::ZeroFilled(s) => write!(f, "zero-filled gap from {} to {}", s.start, s.end),
+            Repair::Trimmed { from, to } => write!(f, "trimmed {} to {}", from, to),
+            Repair::Dropped(br) => write!(f, "dropped {}", br),
+            Repair::Shrunk { from, to } => write!(f, "shrunk size from {} to {}", from, to),
+        }
+    }
+}
+
+/// Builds a FileDescription from runs, in any order, which may not tile the file: each condition
+/// is handled by a Policy.
+#[derive(Debug, Clone, Default)]
+pub struct FileDescriptionBuilder {
+    size: u64,
+    runs: Vec<ByteRun>,
+    policy: Policy,
+}
+
+impl FileDescriptionBuilder {
+    pub fn new(size: u64) -> Self {
+        FileDescriptionBuilder { size, ..Self::default() }
+    }
+
+    /// Adds runs, of lower priority than those added before.
+    pub fn runs<T: IntoIterator<Item=ByteRun>>(&mut self, runs: T) -> &mut Self {
+        self.runs.extend(runs);
+        self
+    }
+
+    pub fn policy(&mut self, policy: Policy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn gaps(&mut self, action: Action) -> &mut Self { self.policy.gaps = action; self }
+    pub fn overlaps(&mut self, action: Action) -> &mut Self { self.policy.overlaps = action; self }
+    pub fn trailing(&mut self, action: Action) -> &mut Self { self.policy.trailing = action; self }
+    pub fn missing_tail(&mut self, action: Action) -> &mut Self { self.policy.missing_tail = action; self }
+    pub fn duplicates(&mut self, action: Action) -> &mut Self { self.policy.duplicates = action; self }
+
+    /// The description, and the repairs made to the runs given.
+    pub fn build(&self) -> Result<(FileDescription, Vec<Repair>), FileDescriptionError> {
+        let policy = &self.policy;
+        let mut repairs = Vec::new();
+        // Fails, or records the repair unless allowed
+        let mut handle = |action: Action, err: FileDescriptionError, repair: Repair| match action {
+            Action::Error => Err(err),
+            Action::Fix => { repairs.push(repair); Ok(()) },
+            Action::Quiet | Action::Allow => Ok(()),
+        };
+        // By file offset, then by priority; the tail of a run split around one nested in it is put
+        // back in
+        let mut pending: BinaryHeap<Reverse<(u64, usize, ByteRun)>> = self.runs.iter().zip(0..)
+            .map(|(&br, prio)| Reverse((br.file_offset, prio, br))).collect();
+        let mut kept: Vec<(ByteRun, usize)> = Vec::with_capacity(pending.len());
+        while let Some(Reverse((_, prio, mut br))) = pending.pop() {
+            if br.file_offset > self.size {
+                handle(policy.trailing, FileDescriptionError::Trailing(br, self.size), Repair::Dropped(br))?;
+                continue;
+            }
+            let (prev, prev_prio) = match kept.last_mut() {
+                Some(x) => x,
+                None => {
+                    if br.file_offset > 0 {
+                        let gap = Segment { start: 0, end: br.file_offset };
+                        handle(policy.gaps, FileDescriptionError::PreGap(br), Repair::ZeroFilled(gap))?;
+                    }
+                    kept.push((br, prio));
+                    continue;
+                },
+            };
+            let prev_prio = *prev_prio;
+            let prev_end = prev.file_offset + prev.len;
+            let end = br.file_offset + br.len;
+            if br.file_offset < prev_end {
+                // A trim may have moved the start of either run, so which one gives way is decided
+                // by priority only
+                let action = if br.file_offset == prev.file_offset { policy.duplicates } else { policy.overlaps };
+                if prev_prio < prio {
+                    if end <= prev_end {
+                        handle(action, FileDescriptionError::Overlap(*prev, br), Repair::Dropped(br))?;
+                        continue;
+                    }
+                    let cut = prev_end - br.file_offset;
+                    let to = ByteRun { file_offset: prev_end, disk_pos: br.disk_pos + cut, len: br.len - cut };
+                    handle(action, FileDescriptionError::Overlap(*prev, br), Repair::Trimmed { from: br, to })?;
+                    br = to;
+                } else {
+                    // The previous run keeps what is before this one, and after it if nested
+                    let from = *prev;
+                    let head = Some(ByteRun { len: br.file_offset - from.file_offset, ..from }).filter(|x| x.len > 0);
+                    let tail = (end < prev_end).then(|| ByteRun {
+                        file_offset: end, disk_pos: from.disk_pos + (end - from.file_offset), len: prev_end - end,
+                    });
+                    if head.is_none() && tail.is_none() {
+                        handle(action, FileDescriptionError::Overlap(from, br), Repair::Dropped(from))?;
+                    }
+                    for &to in head.iter().chain(tail.iter()) {
+                        handle(action, FileDescriptionError::Overlap(from, br), Repair::Trimmed { from, to })?;
+                    }
+                    match head {
+                        Some(head) => { *prev = head; },
+                        None => { kept.pop(); },
+                    }
+                    if let Some(tail) = tail { pending.push(Reverse((tail.file_offset, prev_prio, tail))); }
+                }
+            } else if br.file_offset > prev_end {
+                let gap = Segment { start: prev_end, end: br.file_offset };
+                handle(policy.gaps, FileDescriptionError::Gap(*prev, br), Repair::ZeroFilled(gap))?;
+            }
+            kept.push((br, prio));
+        }
+
+        let mut runs: Vec<ByteRun> = kept.into_iter().map(|(br, _)| br).collect();
+        let mut size = self.size;
+        let last = runs.last_mut().ok_or(FileDescriptionError::Empty)?;
+        let end = last.file_offset + last.len;
+        if end > size {
+            // The last block sometimes needs to be trimmed
+            last.len = size.checked_sub(last.file_offset).ok_or(FileDescriptionError::Trailing(*last, size))?;
+        } else if end < size {
+            handle(policy.missing_tail, FileDescriptionError::Missing(size, end), Repair::Shrunk { from: size, to: end })?;
+            if matches!(policy.missing_tail, Action::Fix | Action::Quiet) { size = end; }
+        }
+        Ok((FileDescription { runs: runs.into_boxed_slice(), size }, repairs))
+    }
+}
+
+impl FileDescription {
+    /// A description of a file which may have holes: ranges, before, between or after the runs,
+    /// which aren't stored anywhere (e.g. a fragment not recovered). Runs may still not overlap,
+    /// nor go past the size.
+    pub fn new_sparse<T: IntoIterator<Item=ByteRun>>(size: u64, runs: T) -> Result<Self, FileDescriptionError> {
+        let runs: Vec<ByteRun> = runs.into_iter().collect();
+        // Unlike for `new`, the last run isn't trimmed to the size
+        if let Some(&br) = runs.iter().find(|br| br.file_offset.checked_add(br.len).map_or(true, |end| end > size)) {
+            return Err(FileDescriptionError::Trailing(br, size));
         }
+        Ok(FileDescriptionBuilder::new(size).runs(runs).policy(Policy::sparse()).build()?.0)
+    }
 
-        if size > off { return Err(FileDescriptionError::Missing(size, off)); }
-        // The last block sometimes needs to be trimmed
-        let mut runs = runs;
-        runs.last_mut().unwrap().len -= off - size;
-        Ok(FileDescription {
-            runs: runs.into_boxed_slice(),
-            size: size,
-        })
+    pub fn new<T: IntoIterator<Item=ByteRun>>(size: u64, runs: T) -> Result<Self, FileDescriptionError> {
+        Ok(FileDescriptionBuilder::new(size).runs(runs).policy(Policy::strict()).build()?.0)
     }
 
     pub fn new_allow_missing<T: IntoIterator<Item=ByteRun>>(size: u64, runs: T) -> Result<Self, FileDescriptionError> {
-        let mut runs: Vec<ByteRun> = runs.into_iter().collect();
-        if runs.len() == 0 { return Err(FileDescriptionError::Empty); }
-        runs.sort_unstable();
-        let runs = runs;
+        Ok(FileDescriptionBuilder::new(size).runs(runs).policy(Policy::allow_missing()).build()?.0)
+    }
 
+    pub fn size(&self) -> u64 { self.size }
+
+    /// The ranges of the file which no run stores, in ascending order.
+    pub fn missing_ranges(&self) -> Vec<Segment<u64>> {
         let mut off = 0;
-        let mut it = runs.iter();
-        let mut br = it.next().unwrap();
-        // First check the first ByteRun starts at 0
-        if br.file_offset != 0 { return Err(FileDescriptionError::PreGap(*br)); }
-        off += br.len;
-        // Then check all the following ByteRun-s come one after another
-        for br2 in it {
-            if off > size { return Err(FileDescriptionError::Trailing(*br2, size)); }
-            if br2.file_offset > off { return Err(FileDescriptionError::Gap(*br, *br2)); }
-            else if br2.file_offset < off { return Err(FileDescriptionError::Overlap(*br, *br2)); }
-            br = br2;
-            off += br.len;
+        let mut ranges = Vec::new();
+        for br in self.runs.iter().chain(Some(&ByteRun { file_offset: self.size, disk_pos: 0, len: 0 })) {
+            if br.file_offset > off { ranges.push(Segment { start: off, end: br.file_offset }); }
+            off = br.file_offset + br.len;
         }
+        ranges
+    }
 
-        // The last block sometimes needs to be trimmed
-        let mut runs = runs;
-        runs.last_mut().unwrap().len -= off.saturating_sub(size);
-        Ok(FileDescription {
-            runs: runs.into_boxed_slice(),
-            size: off.min(size),
-        })
+    /// Whether runs store every byte of the file, i.e. it has no holes.
+    pub fn is_complete(&self) -> bool {
+        self.runs.iter().map(|br| br.len).sum::<u64>() == self.size
     }
 
-    pub fn size(&self) -> u64 { self.size }
+    /// The ranges of file offsets stored in the given disk range, in ascending order.
+    pub fn file_ranges(&self, disk: &Segment<u64>) -> Vec<Segment<u64>> {
+        let mut ranges: Vec<Segment<u64>> = self.runs.iter().filter_map(|br| {
+            let start = disk.start.max(br.disk_pos);
+            let end = disk.end.min(br.disk_pos + br.len);
+            if start < end {
+                Some(Segment { start: br.file_offset + (start - br.disk_pos), end: br.file_offset + (end - br.disk_pos) })
+            } else { None }
+        }).collect();
+        ranges.sort_unstable();
+        ranges
+    }
 }
 
 impl AsRef<[ByteRun]> for FileDescription {
@@ -144,25 +368,13 @@ impl<'a> Desc<'a> for FileDescription {
     type DescReader = FileDescriptionPos<'a>;
 
     fn at_pos(&'a self, pos: u64) -> FileDescriptionPos<'a> {
-        if pos > self.size {
-            FileDescriptionPos {
-                _ref: &self,
-                pos,
-                cur_run: self.runs.len(),
-                offset_in_run: 0,
-            }
-        } else {
-            let cur_run = match self.runs.binary_search_by_key(&pos, |br| br.file_offset) {
-                    Ok(x) => x,  // We're at the beginning of this slice.
-                    Err(x) => x-1,  // We could be inserted after this slice, which
-                                    // means we're somewhere within it.
-                };
-            FileDescriptionPos {
-                _ref: &self,
-                pos,
-                cur_run,
-                offset_in_run: pos - self.runs[cur_run].file_offset,
-            }
+        // The run the position is in, or the first one after it (if in a hole), if any
+        let cur_run = self.runs.partition_point(|br| br.file_offset + br.len <= pos);
+        FileDescriptionPos {
+            _ref: self,
+            pos,
+            cur_run,
+            offset_in_run: self.runs.get(cur_run).map_or(0, |br| pos.saturating_sub(br.file_offset)),
         }
     }
 }
@@ -175,23 +387,37 @@ impl<'a> From<&'a FileDescription> for FileDescriptionPos<'a> {
 
 impl<'a> DescRead for FileDescriptionPos<'a> {
     fn desc_read(&mut self) -> ByteRun {
-        if self.cur_run != self._ref.runs.len() {
-            ByteRun {
+        match self._ref.runs.get(self.cur_run) {
+            Some(br) if br.file_offset <= self.pos => ByteRun {
                 file_offset: self.pos,
-                disk_pos: self._ref.runs[self.cur_run].disk_pos + self.offset_in_run,
-                len: self._ref.runs[self.cur_run].len - self.offset_in_run,
-            }
-        } else {
-            ByteRun {
+                disk_pos: br.disk_pos + self.offset_in_run,
+                len: br.len - self.offset_in_run,
+            },
+            _ => ByteRun {
                 file_offset: self.pos,
                 disk_pos: 0,
                 len: 0,
-            }
+            },
         }
     }
 
+    fn desc_hole(&mut self) -> Option<Segment<u64>> {
+        if self.pos >= self._ref.size { return None; }
+        let (start, end) = match self._ref.runs.get(self.cur_run) {
+            Some(br) if br.file_offset <= self.pos => { return None; },
+            Some(br) => (self.cur_run.checked_sub(1).map_or(0, |i| self._ref.runs[i].file_offset + self._ref.runs[i].len), br.file_offset),
+            None => (self._ref.runs.last().map_or(0, |br| br.file_offset + br.len), self._ref.size),
+        };
+        Some(Segment { start, end })
+    }
+
     fn adv(&mut self, n: usize) {
         let n = n as u64;
+        if let Some(hole) = self.desc_hole() {
+            if n > hole.end - self.pos { panic!("Should only read up to end of hole.") }
+            self.pos += n;
+            return;
+        }
         let rem = self._ref.runs[self.cur_run].len - self.offset_in_run;
         if n < rem {
             self.pos += n;
@@ -207,6 +433,7 @@ impl<'a> DescRead for FileDescriptionPos<'a> {
 }
 
 impl<'a> Seek for FileDescriptionPos<'a> {
+    #[allow(clippy::swap_with_temporary, clippy::needless_return)]
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         let (base_pos, offset) = match pos {
             SeekFrom::Start(x) => { mem::swap(self, &mut self._ref.at_pos(x)); return Ok(x); }
@@ -305,3 +532,167 @@ fn test_file_description_ref_at_pos() {
     assert_eq!(brp.cur_run, 3);
     assert_eq!(brp.offset_in_run, 0);
 }
+
+#[test]
+fn test_file_description_sparse() {
+    let br = FileDescription::new_sparse(200, vec![
+        ByteRun { file_offset: 100, disk_pos: 2000, len: 50 },
+        ByteRun { file_offset: 20, disk_pos: 16000, len: 30 },
+    ]).unwrap();
+    assert_eq!(br.size(), 200);
+    assert!(!br.is_complete());
+    assert_eq!(br.missing_ranges(), vec![Segment::new(0, 20), Segment::new(50, 100), Segment::new(150, 200)]);
+
+    let mut brp = br.at_pos(0);
+    assert_eq!(brp.desc_read().len, 0);
+    assert_eq!(brp.desc_hole(), Some(Segment::new(0, 20)));
+    brp.adv(20);
+    assert_eq!(brp.desc_hole(), None);
+    assert_eq!(brp.desc_read(), ByteRun { file_offset: 20, disk_pos: 16000, len: 30 });
+    brp.adv(30);
+    assert_eq!(brp.desc_hole(), Some(Segment::new(50, 100)));
+    let mut brp = br.at_pos(120);
+    assert_eq!(brp.desc_read(), ByteRun { file_offset: 120, disk_pos: 2020, len: 30 });
+    brp.adv(30);
+    assert_eq!(brp.desc_hole(), Some(Segment::new(150, 200)));
+    brp.adv(50);
+    assert_eq!(brp.desc_hole(), None);
+    assert_eq!(brp.desc_read().len, 0);
+
+    if let Err(FileDescriptionError::Overlap(x, y)) = FileDescription::new_sparse(200, vec![
+        ByteRun { file_offset: 40, disk_pos: 2000, len: 50 },
+        ByteRun { file_offset: 20, disk_pos: 16000, len: 30 },
+    ]) {
+        assert_eq!(x, ByteRun { file_offset: 20, disk_pos: 16000, len: 30 });
+        assert_eq!(y, ByteRun { file_offset: 40, disk_pos: 2000, len: 50 });
+    } else { panic!(); }
+    if let Err(FileDescriptionError::Trailing(x, 100)) = FileDescription::new_sparse(100, vec![
+        ByteRun { file_offset: 80, disk_pos: 2000, len: 50 },
+    ]) {
+        assert_eq!(x, ByteRun { file_offset: 80, disk_pos: 2000, len: 50 });
+    } else { panic!(); }
+    // A hole at the start and a stray run wholly past the size
+    let stray = ByteRun { file_offset: 150, disk_pos: 0, len: 10 };
+    let err = FileDescription::new_sparse(100, vec![ByteRun { file_offset: 20, disk_pos: 16000, len: 30 }, stray]).err();
+    assert_let!(Some(FileDescriptionError::Trailing(x, 100)) = err, { assert_eq!(x, stray); });
+    let err = FileDescription::new_sparse(100, vec![stray]).err();
+    assert_let!(Some(FileDescriptionError::Trailing(x, 100)) = err, { assert_eq!(x, stray); });
+
+    let br = FileDescription::new(150, vec![
+        ByteRun { file_offset: 0, disk_pos: 16000, len: 50 },
+        ByteRun { file_offset: 50, disk_pos: 8000, len: 100 },
+    ]).unwrap();
+    assert!(br.is_complete());
+    assert_eq!(br.missing_ranges(), vec![]);
+}
+
+#[test]
+fn test_file_description_builder() {
+    let runs = vec![
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 50 },
+        ByteRun { file_offset: 100, disk_pos: 3000, len: 50 },
+        ByteRun { file_offset: 40, disk_pos: 2000, len: 30 },
+        ByteRun { file_offset: 100, disk_pos: 4000, len: 20 },
+        ByteRun { file_offset: 300, disk_pos: 5000, len: 10 },
+    ];
+    let (desc, repairs) = FileDescriptionBuilder::new(200).runs(runs.clone()).policy(Policy::repair()).build().unwrap();
+    assert_eq!(desc.size(), 150);
+    assert_eq!(desc.as_ref(), &[
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 50 },
+        ByteRun { file_offset: 50, disk_pos: 2010, len: 20 },
+        ByteRun { file_offset: 100, disk_pos: 3000, len: 50 },
+    ]);
+    assert_eq!(repairs, vec![
+        Repair::Trimmed { from: runs[2], to: ByteRun { file_offset: 50, disk_pos: 2010, len: 20 } },
+        Repair::ZeroFilled(Segment::new(70, 100)),
+        Repair::Dropped(runs[3]),
+        Repair::Dropped(runs[4]),
+        Repair::Shrunk { from: 200, to: 150 },
+    ]);
+    assert_eq!(desc.missing_ranges(), vec![Segment::new(70, 100)]);
+
+    // Allowed conditions are repaired silently, or left as holes
+    let (desc, repairs) = FileDescriptionBuilder::new(200).runs(runs.clone()).policy(Policy::sparse())
+        .overlaps(Action::Allow).duplicates(Action::Allow).trailing(Action::Allow).build().unwrap();
+    assert_eq!(desc.size(), 200);
+    assert_eq!(desc.missing_ranges(), vec![Segment::new(70, 100), Segment::new(150, 200)]);
+    assert_eq!(repairs, vec![]);
+
+    // The run given first wins an overlap
+    let (desc, _) = FileDescriptionBuilder::new(100).overlaps(Action::Fix).runs(vec![
+        ByteRun { file_offset: 40, disk_pos: 2000, len: 60 },
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 50 },
+    ]).build().unwrap();
+    assert_eq!(desc.as_ref(), &[
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 40 },
+        ByteRun { file_offset: 40, disk_pos: 2000, len: 60 },
+    ]);
+
+    // A run nested in one of lower priority splits it, keeping both parts
+    let nested = vec![
+        ByteRun { file_offset: 20, disk_pos: 5000, len: 10 },
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 100 },
+    ];
+    let (desc, repairs) = FileDescriptionBuilder::new(100).runs(nested.clone()).policy(Policy::repair()).build().unwrap();
+    assert_eq!(desc.size(), 100);
+    assert!(desc.is_complete());
+    assert_eq!(desc.as_ref(), &[
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 20 },
+        ByteRun { file_offset: 20, disk_pos: 5000, len: 10 },
+        ByteRun { file_offset: 30, disk_pos: 1030, len: 70 },
+    ]);
+    assert_eq!(repairs, vec![
+        Repair::Trimmed { from: nested[1], to: ByteRun { file_offset: 0, disk_pos: 1000, len: 20 } },
+        Repair::Trimmed { from: nested[1], to: ByteRun { file_offset: 30, disk_pos: 1030, len: 70 } },
+    ]);
+    assert!(FileDescriptionBuilder::new(100).runs(nested).overlaps(Action::Fix).build().is_ok());
+
+    // A run whose start a trim moved still gives way to one of higher priority starting there
+    let (desc, _) = FileDescriptionBuilder::new(100).policy(Policy::repair()).runs(vec![
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 50 },
+        ByteRun { file_offset: 50, disk_pos: 2000, len: 50 },
+        ByteRun { file_offset: 40, disk_pos: 3000, len: 30 },
+    ]).build().unwrap();
+    assert_eq!(desc.as_ref(), &[
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 50 },
+        ByteRun { file_offset: 50, disk_pos: 2000, len: 50 },
+    ]);
+
+    let err = FileDescriptionBuilder::new(200).runs(runs.clone()).policy(Policy::repair()).duplicates(Action::Error).build().err();
+    assert_let!(Some(FileDescriptionError::Overlap(x, y)) = err, {
+        assert_eq!((x, y), (runs[1], runs[3]));
+    });
+
+    // A run starting right at the size is kept with no bytes, as `new` always did
+    let at_size = vec![
+        ByteRun { file_offset: 0, disk_pos: 1000, len: 100 },
+        ByteRun { file_offset: 100, disk_pos: 3000, len: 50 },
+    ];
+    let desc = FileDescription::new(100, at_size.clone()).unwrap();
+    assert_eq!(desc.as_ref()[1], ByteRun { file_offset: 100, disk_pos: 3000, len: 0 });
+    assert!(FileDescription::new_allow_missing(100, at_size).is_ok());
+
+    // One starting past the size is trailing, even if it's the first
+    let past_size = vec![ByteRun { file_offset: 150, disk_pos: 0, len: 10 }];
+    let err = FileDescription::new(100, past_size.clone()).err();
+    assert_let!(Some(FileDescriptionError::Trailing(x, 100)) = err, { assert_eq!(x, past_size[0]); });
+    let err = FileDescriptionBuilder::new(100).runs(past_size.clone()).policy(Policy::repair()).build().err();
+    assert_let!(Some(FileDescriptionError::Empty) = err);
+
+    // Cutting down a file PhotoRec didn't recover to its end isn't a repair
+    let (desc, repairs) = FileDescriptionBuilder::new(200).runs(runs[..1].to_vec()).policy(Policy::allow_missing()).build().unwrap();
+    assert_eq!(desc.size(), 50);
+    assert_eq!(repairs, vec![]);
+}
+
+#[test]
+fn test_file_description_file_ranges() {
+    let br = FileDescription::new(150, vec![
+        ByteRun { file_offset: 0, disk_pos: 16000, len: 50 },
+        ByteRun { file_offset: 50, disk_pos: 8000, len: 50 },
+        ByteRun { file_offset: 100, disk_pos: 8050, len: 50 },
+    ]).unwrap();
+    assert_eq!(br.file_ranges(&Segment::new(8040, 8060)), vec![Segment::new(90, 100), Segment::new(100, 110)]);
+    assert_eq!(br.file_ranges(&Segment::new(15990, 16010)), vec![Segment::new(0, 10)]);
+    assert_eq!(br.file_ranges(&Segment::new(0, 8000)), vec![]);
+}
@@ -0,0 +1,155 @@
+//
+// Opening disk images of any supported format as a single device.
+//
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use super::ewf::{EwfImage, EwfError, is_ewf};
+use super::split_image::SplitImage;
+use super::virtual_disk::{VirtualDisk, VirtualDiskError, detect_format};
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("Error reading: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Ewf(#[from] EwfError),
+    #[error(transparent)]
+    VirtualDisk(#[from] VirtualDiskError),
+    #[error("No image given for a report")]
+    NoImage,
+}
+
+/// A disk image, read (impl Read + Seek) as the device it holds.
+pub enum Image {
+    /// A raw image, possibly split in segments
+    Raw(SplitImage<File>),
+    Ewf(EwfImage<File>),
+    /// A qcow2, VHD or VMDK disk
+    Virtual(VirtualDisk<File>),
+}
+
+impl Image {
+    /// Opens the image at `path` (the first segment, for split images), by its format.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let mut f = File::open(path)?;
+        if is_ewf(&mut f)? {
+            Ok(Image::Ewf(EwfImage::open(path)?))
+        } else if detect_format(&mut f)?.is_some() {
+            Ok(Image::Virtual(VirtualDisk::new(f)?))
+        } else {
+            Ok(Image::Raw(SplitImage::open(path)?))
+        }
+    }
+
+    /// Whether `path` names the first segment of an EWF image, by its extension.
+    pub fn is_ewf_name<P: AsRef<Path>>(path: P) -> bool {
+        path.as_ref().extension().and_then(|x| x.to_str()).is_some_and(|x| x.eq_ignore_ascii_case("e01"))
+    }
+}
+
+impl Read for Image {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Image::Raw(x) => x.read(buf),
+            Image::Ewf(x) => x.read(buf),
+            Image::Virtual(x) => x.read(buf),
+        }
+    }
+}
+
+impl Seek for Image {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Image::Raw(x) => x.seek(pos),
+            Image::Ewf(x) => x.seek(pos),
+            Image::Virtual(x) => x.seek(pos),
+        }
+    }
+}
+
+/// Finds the image to read a report from, by the `image_filename` it records, rewritten by the
+/// rule with the longest matching prefix. Lets reports made on other machines, or from other
+/// copies of the disk, be read from where their images are now.
+#[derive(Debug, Clone, Default)]
+pub struct ImageResolver {
+    rules: Vec<(String, PathBuf)>,
+    default: Option<PathBuf>,
+}
+
+impl ImageResolver {
+    pub fn new() -> Self { Self::default() }
+
+    /// Rewrites image file names starting with the prefix `from` (as whole path components, so
+    /// `/dev/sdb` doesn't match `/dev/sdb2`) to start with `to` instead. The longest prefix
+    /// matching wins.
+    pub fn add_rule<S: Into<String>, P: Into<PathBuf>>(&mut self, from: S, to: P) -> &mut Self {
+        self.rules.push((from.into(), to.into()));
+        self
+    }
+
+    /// The image for reports whose image no rule rewrites, instead of the one they record; except
+    /// that a recorded EWF image is still used if it exists.
+    pub fn set_default<P: Into<PathBuf>>(&mut self, default: Option<P>) -> &mut Self {
+        self.default = default.map(Into::into);
+        self
+    }
+
+    fn rewrite(&self, image_filename: &str) -> Option<PathBuf> {
+        let (from, to) = self.rules.iter().filter(|(from, _)| {
+            image_filename.strip_prefix(from.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || from.ends_with('/'))
+        }).max_by_key(|(from, _)| from.len())?;
+        let rest = image_filename[from.len()..].trim_start_matches('/');
+        Some(if rest.is_empty() { to.clone() } else { to.join(rest) })
+    }
+
+    /// The path of the image for a report recording `image_filename`, if any.
+    pub fn resolve(&self, image_filename: Option<&str>) -> Option<PathBuf> {
+        if let Some(path) = image_filename.and_then(|x| self.rewrite(x)) {
+            return Some(path);
+        }
+        let recorded = image_filename.map(PathBuf::from);
+        match self.default {
+            Some(ref default) => Some(recorded.filter(|p| Image::is_ewf_name(p) && p.exists())
+                .unwrap_or_else(|| default.clone())),
+            None => recorded,
+        }
+    }
+
+    /// Opens the image for a report recording `image_filename`.
+    pub fn open(&self, image_filename: Option<&str>) -> Result<Image, ImageError> {
+        Image::open(self.resolve(image_filename).ok_or(ImageError::NoImage)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImageResolver;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_image_resolver() {
+        let mut resolver = ImageResolver::new();
+        resolver.add_rule("/dev/sdb", "/evidence/case12/disk.E01")
+            .add_rule("/mnt/", "/media/backup")
+            .add_rule("/mnt/images", "/srv/images");
+        let resolve = |x| resolver.resolve(x);
+        assert_eq!(resolve(Some("/dev/sdb")), Some(PathBuf::from("/evidence/case12/disk.E01")));
+        // Not a whole path component
+        assert_eq!(resolve(Some("/dev/sdb2")), Some(PathBuf::from("/dev/sdb2")));
+        assert_eq!(resolve(Some("/mnt/x.dd")), Some(PathBuf::from("/media/backup/x.dd")));
+        assert_eq!(resolve(Some("/mnt/images/y.dd")), Some(PathBuf::from("/srv/images/y.dd")));
+        assert_eq!(resolve(None), None);
+
+        resolver.set_default(Some("disk.img"));
+        assert_eq!(resolver.resolve(Some("/dev/sdb")), Some(PathBuf::from("/evidence/case12/disk.E01")));
+        assert_eq!(resolver.resolve(Some("/dev/sdc")), Some(PathBuf::from("disk.img")));
+        assert_eq!(resolver.resolve(Some("/nonexistent/x.E01")), Some(PathBuf::from("disk.img")));
+        assert_eq!(resolver.resolve(None), Some(PathBuf::from("disk.img")));
+    }
+}
@@ -1,11 +1,47 @@
+mod coverage;
+mod ddrescue;
+mod diff;
+mod disk_index;
+mod ewf;
+mod features;
 mod file_description;
+mod image;
+mod lint;
+mod merge;
+mod num;
+mod overlap;
+mod partition;
 mod reader;
 mod report;
+mod resolve;
+mod schedule;
+mod set_ops;
+mod split_image;
+#[cfg(test)]
+mod test_util;
+mod tolerant;
+mod virtual_disk;
 
-pub use crate::file_description::{ByteRun, FileDescription, FileDescriptionPos, FileDescriptionError, Desc};
+pub use crate::file_description::{ByteRun, FileDescription, FileDescriptionPos, FileDescriptionError, FileDescriptionBuilder, Policy, Action, Repair, Desc, DescRead, TrimmedLastRun};
+pub use crate::coverage::Coverage;
+pub use crate::ddrescue::{Mapfile, MapfileError, Block, BlockStatus, RescueStatus};
+pub use crate::disk_index::{DiskIndex, Hit};
+pub use crate::diff::{diff, relate, relate_with, DiffEntry, DiffKind};
+pub use crate::ewf::{EwfImage, EwfError, ewf_segment_paths, is_ewf};
+pub use crate::features::{Feature, FeatureError, parse_features, annotate};
+pub use crate::image::{Image, ImageError, ImageResolver};
+pub use crate::lint::{lint, Lint, Problem, Severity};
+pub use crate::merge::{merge, merge_with, Merged, MergeConflict, ConflictKind};
+pub use crate::partition::{PartitionTable, Partition, PartitionKind, PartitionScheme, PartitionError};
 pub use crate::reader::ByteRunsReader;
-pub use crate::report::{ReportXml, ReportXmlError};
+pub use crate::report::{ReportXml, ReportXmlError, Provenance, Origin, Location};
+pub use crate::resolve::{Candidate, Resolution, Resolver, Split, LargerFilesize, FewerFragments, FirstReport, ContentCheck, DropBoth};
+pub use crate::schedule::{schedule, extract, Region};
+pub use crate::set_ops::{union, intersection, difference, symmetric_difference};
+pub use crate::split_image::{SplitImage, segment_paths};
+pub use crate::tolerant::{TolerantReader, ReadPolicy, Fill};
 
+pub use crate::virtual_disk::{VirtualDisk, VirtualDiskError, DiskFormat, detect_format};
 #[cfg(test)]
 #[macro_use]
 extern crate assert_let;
@@ -0,0 +1,225 @@
+//
+// Sanity checks of a report: problems in its fileobjects which PhotoRec never
+// makes, and so point to corruption or editing mistakes.
+//
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use super::coverage::sweep;
+use super::report::ReportXml;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Suspicious, but the fileobject can still be read
+    Warning,
+    /// The fileobject can't be read as given
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem with a fileobject; runs and other fileobjects are given by their index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The fileobject couldn't be parsed
+    Malformed(String),
+    /// The `img_offset` or `len` of a run isn't a multiple of the sector size
+    Misaligned { run: usize, field: &'static str, value: u64 },
+    ZeroLength { run: usize },
+    /// Runs of this and another fileobject (or of this one, if it's its own index) claim the same
+    /// disk bytes; from `first` on, `bytes` of them in all
+    Overlap { other: usize, other_name: String, first: u64, bytes: u64 },
+    /// A run ends at `end`, past the end of the image
+    BeyondImage { run: usize, end: u64 },
+    /// An earlier fileobject has the same name
+    DuplicateName { first: usize },
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::Misaligned { field: "len", .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Malformed(e) => write!(f, "malformed: {}", e),
+            Problem::Misaligned { run, field, value } => write!(f, "run {} has {} {} not aligned to the sector size", run, field, value),
+            Problem::ZeroLength { run } => write!(f, "run {} has zero length", run),
+            Problem::Overlap { other_name, first, bytes, .. } => write!(f, "overlaps {} in {} bytes from {}", other_name, bytes, first),
+            Problem::BeyondImage { run, end } => write!(f, "run {} ends at {}, past the end of the image", run, end),
+            Problem::DuplicateName { first } => write!(f, "same name as fileobject {}", first),
+        }
+    }
+}
+
+/// A problem with the fileobject of the index (its ordinal in the report), and its name if it
+/// could be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub index: usize,
+    pub file_name: Option<String>,
+    pub severity: Severity,
+    pub problem: Problem,
+}
+
+/// Checks every fileobject of the report, returning the problems found, by fileobject. Runs are
+/// checked for alignment to the sector size given in the report (512 if none, or 0), and against
+/// the image size, if given; a run ending past the largest disk position is always beyond it.
+pub fn lint(report: &ReportXml) -> Vec<Lint> {
+    let sectorsize = report.sectorsize().unwrap_or(512);
+    let image_size = report.image_size();
+    let mut lints = Vec::new();
+    let mut names: Vec<Option<String>> = Vec::new();
+    let mut first_with_name: HashMap<String, usize> = HashMap::new();
+    let mut runs = Vec::new();
+    for (i, r) in report.iter_raw().enumerate() {
+        let mut push = |name: Option<&String>, problem: Problem| lints.push(Lint {
+            index: i, file_name: name.cloned(), severity: problem.severity(), problem,
+        });
+        let (name, _, brs) = match r {
+            Ok(x) => x,
+            Err(mut e) => {
+                // The lint gives the fileobject already
+                let name = e.take_location().and_then(|x| x.file_name);
+                push(name.as_ref(), Problem::Malformed(e.to_string()));
+                names.push(None);
+                continue;
+            },
+        };
+        match first_with_name.get(&name) {
+            Some(&first) => push(Some(&name), Problem::DuplicateName { first }),
+            None => { first_with_name.insert(name.clone(), i); },
+        }
+        for (j, br) in brs.into_iter().enumerate() {
+            if br.len == 0 { push(Some(&name), Problem::ZeroLength { run: j }); }
+            if br.disk_pos % sectorsize != 0 {
+                push(Some(&name), Problem::Misaligned { run: j, field: "img_offset", value: br.disk_pos });
+            }
+            if br.len % sectorsize != 0 {
+                push(Some(&name), Problem::Misaligned { run: j, field: "len", value: br.len });
+            }
+            match br.disk_pos.checked_add(br.len) {
+                Some(end) if image_size.map_or(true, |size| end <= size) => {},
+                end => push(Some(&name), Problem::BeyondImage { run: j, end: end.unwrap_or(u64::MAX) }),
+            }
+            runs.push((br, (i, j)));
+        }
+        names.push(Some(name));
+    }
+
+    // Each pair of fileobjects claiming the same region, by (first, bytes); regions are claimed by
+    // runs, so a fileobject overlapping itself shows up too. A SegmentTree refuses intersecting
+    // segments, so the runs can't go in one as they are: `sweep` first cuts them into disjoint
+    // regions, and gives the SegmentTree of these, with the runs claiming each
+    let mut overlaps: BTreeMap<(usize, usize), (u64, u64)> = BTreeMap::new();
+    for (seg, claims) in sweep(runs).iter() {
+        for (a, &(x, _)) in claims.iter().enumerate() {
+            for &(y, _) in &claims[a + 1..] {
+                let e = overlaps.entry((x, y)).or_insert((seg.start, 0));
+                e.1 += seg.end - seg.start;
+            }
+        }
+    }
+    for ((x, y), (first, bytes)) in overlaps {
+        let mut push = |i: usize, other: usize| {
+            let problem = Problem::Overlap { other, other_name: names[other].clone().unwrap(), first, bytes };
+            lints.push(Lint { index: i, file_name: names[i].clone(), severity: problem.severity(), problem });
+        };
+        push(x, y);
+        if x != y { push(y, x); }
+    }
+    lints.sort_by_key(|l| l.index);
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, Severity, Problem};
+    use crate::report::ReportXml;
+
+    #[test]
+    fn test_lint() {
+        let s = r##"<?xml version='1.0' encoding='UTF-8'?>
+    <dfxml xmloutputversion='1.0'>
+      <source>
+        <sectorsize>512</sectorsize>
+        <image_size>10240</image_size>
+      </source>
+      <fileobject>
+        <filename>f1.jpg</filename>
+        <filesize>1000</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='0' len='1024'/>
+        </byte_runs>
+      </fileobject>
+      <fileobject>
+        <filename>f2.jpg</filename>
+        <filesize>1500</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='512' len='1000'/>
+          <byte_run offset='1000' img_offset='2050' len='500'/>
+          <byte_run offset='1500' img_offset='4096' len='0'/>
+        </byte_runs>
+      </fileobject>
+      <fileobject>
+        <filename>f3.jpg</filename>
+      </fileobject>
+      <fileobject>
+        <filename>f1.jpg</filename>
+        <filesize>1024</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='9728' len='1024'/>
+        </byte_runs>
+      </fileobject>
+    </dfxml>"##;
+        let report = ReportXml::parse(s.as_bytes()).unwrap();
+        let lints = lint(&report);
+        let found = lints.iter().map(|l| (l.index, l.file_name.as_deref(), l.severity, l.problem.clone())).collect::<Vec<_>>();
+        assert_eq!(&found[..], &[
+            (0, Some("f1.jpg"), Severity::Error, Problem::Overlap { other: 1, other_name: "f2.jpg".to_owned(), first: 512, bytes: 512 }),
+            (1, Some("f2.jpg"), Severity::Warning, Problem::Misaligned { run: 0, field: "len", value: 1000 }),
+            (1, Some("f2.jpg"), Severity::Error, Problem::Misaligned { run: 1, field: "img_offset", value: 2050 }),
+            (1, Some("f2.jpg"), Severity::Warning, Problem::Misaligned { run: 1, field: "len", value: 500 }),
+            (1, Some("f2.jpg"), Severity::Error, Problem::ZeroLength { run: 2 }),
+            (1, Some("f2.jpg"), Severity::Error, Problem::Overlap { other: 0, other_name: "f1.jpg".to_owned(), first: 512, bytes: 512 }),
+            (2, Some("f3.jpg"), Severity::Error, Problem::Malformed("Missing field filesize in xml".to_owned())),
+            (3, Some("f1.jpg"), Severity::Error, Problem::DuplicateName { first: 0 }),
+            (3, Some("f1.jpg"), Severity::Error, Problem::BeyondImage { run: 0, end: 10752 }),
+        ]);
+        assert_eq!(lints[0].problem.to_string(), "overlaps f2.jpg in 512 bytes from 512");
+    }
+
+    #[test]
+    fn test_lint_corrupted() {
+        // No sector size to align to, and a run overflowing the disk positions
+        let s = r##"<?xml version='1.0' encoding='UTF-8'?>
+    <dfxml xmloutputversion='1.0'>
+      <source>
+        <sectorsize>0</sectorsize>
+      </source>
+      <fileobject>
+        <filename>f1.jpg</filename>
+        <filesize>1024</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='18446744073709551104' len='1024'/>
+        </byte_runs>
+      </fileobject>
+    </dfxml>"##;
+        let report = ReportXml::parse(s.as_bytes()).unwrap();
+        assert_eq!(report.sectorsize(), None);
+        let found = lint(&report).into_iter().map(|l| l.problem).collect::<Vec<_>>();
+        assert_eq!(found, vec![Problem::BeyondImage { run: 0, end: u64::MAX }]);
+    }
+}
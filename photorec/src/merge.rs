@@ -0,0 +1,351 @@
+//
+// Merging several reports into as few conflict-free reports as we can. Files
+// extending one another (allowing for a trimmed last run, see `TrimmedLastRun`)
+// are first collapsed to the largest description, then
+// the remaining incompatible descriptions are split between reports by coloring
+// their conflict graph.
+//
+use std::cmp::{Reverse, max, min};
+use std::collections::BTreeSet;
+use std::iter::FromIterator;
+use std::fmt;
+
+use segment_tree::{Segment, SegmentArrayTreeError};
+
+use super::diff::add_pair;
+use super::file_description::{FileDescription, TrimmedLastRun};
+use super::overlap::OverlapIndex;
+use super::report::{ReportXml, Provenance, Origin};
+use super::resolve::{Candidate, Resolution, Resolver, Split};
+use super::set_ops::{flatten, group, Entry};
+
+/// The kind of SegmentArrayTreeError two conflicting file descriptions give, without the indexes
+/// which only make sense within the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConflictKind {
+    IntersectingSegment,
+    OverlappingSegmentArrays,
+    IncompatibleSegmentArrays,
+}
+
+impl ConflictKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictKind::IntersectingSegment => "intersecting_segment",
+            ConflictKind::OverlappingSegmentArrays => "overlapping_segment_arrays",
+            ConflictKind::IncompatibleSegmentArrays => "incompatible_segment_arrays",
+        }
+    }
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&SegmentArrayTreeError> for ConflictKind {
+    fn from(e: &SegmentArrayTreeError) -> Self {
+        match e {
+            SegmentArrayTreeError::IntersectingSegment(_) => ConflictKind::IntersectingSegment,
+            SegmentArrayTreeError::OverlappingSegmentArrays(_, _) => ConflictKind::OverlappingSegmentArrays,
+            SegmentArrayTreeError::IncompatibleSegmentArrays(_) => ConflictKind::IncompatibleSegmentArrays,
+        }
+    }
+}
+
+/// Two file descriptions which couldn't be put in the same output report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub report_a: String,
+    pub name_a: String,
+    pub report_b: String,
+    pub name_b: String,
+    pub kind: ConflictKind,
+    /// The disk ranges used by both, in ascending order
+    pub overlaps: Vec<Segment<u64>>,
+    /// How the Resolver settled the conflict. A description is dropped if any of its conflicts
+    /// drops it, so one kept here may still be dropped by another conflict.
+    pub resolution: Resolution,
+}
+
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub reports: Vec<ReportXml>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// The disk ranges used by both descriptions, in ascending order.
+fn overlapping_ranges(a: &FileDescription, b: &FileDescription) -> Vec<Segment<u64>> {
+    let mut a: Vec<Segment<u64>> = a.as_ref().iter().map(Segment::from).collect();
+    let mut b: Vec<Segment<u64>> = b.as_ref().iter().map(Segment::from).collect();
+    a.sort_unstable();
+    b.sort_unstable();
+    let (mut i, mut j) = (0, 0);
+    let mut ret = Vec::new();
+    while i < a.len() && j < b.len() {
+        let (start, end) = (max(a[i].start, b[j].start), min(a[i].end, b[j].end));
+        if start < end { ret.push(Segment { start, end }); }
+        if a[i].end < b[j].end { i += 1; } else { j += 1; }
+    }
+    ret
+}
+
+/// Colors the graph using the DSatur heuristic: repeatedly color the node with the most distinct
+/// colors among its neighbours, breaking ties by degree and then by node index, with the smallest
+/// color not used by any neighbour.
+fn color(adj: &[Vec<usize>]) -> Vec<usize> {
+    let mut colors: Vec<Option<usize>> = vec![None; adj.len()];
+    let mut neighbour_colors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); adj.len()];
+    let key = |n: usize, neighbour_colors: &[BTreeSet<usize>]| (Reverse(neighbour_colors[n].len()), Reverse(adj[n].len()), n);
+    let mut queue: BTreeSet<_> = (0..adj.len()).map(|n| key(n, &neighbour_colors)).collect();
+    while let Some(k) = queue.iter().next().cloned() {
+        queue.remove(&k);
+        let n = k.2;
+        let c = (0..).find(|c| !neighbour_colors[n].contains(c)).unwrap();
+        colors[n] = Some(c);
+        for &m in adj[n].iter() {
+            if colors[m].is_some() || neighbour_colors[m].contains(&c) { continue; }
+            queue.remove(&key(m, &neighbour_colors));
+            neighbour_colors[m].insert(c);
+            queue.insert(key(m, &neighbour_colors));
+        }
+    }
+    colors.into_iter().map(|c| c.unwrap()).collect()
+}
+
+/// Merges the reports, given with their names, preferring supersets, into a small number of
+/// reports without conflicts within any of them. Each output fileobject records its Provenance.
+/// Every pair of conflicting descriptions which
+/// ended up in different reports is returned as well. The result doesn't depend on the order of
+/// the reports: the files in each output report are sorted by their byte runs.
+pub fn merge<R, I>(reports: R) -> Merged
+    where R: IntoIterator<Item=(String, I)>, I: IntoIterator<Item=(String, FileDescription)>
+{
+    merge_with(reports, Split)
+}
+
+/// Like `merge`, but every conflict is first given to the resolver, which may drop either
+/// description (or both) instead of splitting them between reports. Each conflict records the
+/// resolution chosen for it. Drops are decided from all the resolutions together: a description
+/// is dropped if any conflict drops it, whatever the order of the conflicts.
+pub fn merge_with<R, I, S>(reports: R, mut resolver: S) -> Merged
+    where R: IntoIterator<Item=(String, I)>, I: IntoIterator<Item=(String, FileDescription)>, S: Resolver
+{
+    let (report_names, reports): (Vec<String>, Vec<I>) = reports.into_iter().unzip();
+    let (_, entries) = flatten(reports);
+    let origin = |i: usize| Origin { report: report_names[entries[i].report].clone(), filename: entries[i].name.clone() };
+    let mut selected: Vec<(usize, Provenance)> = Vec::new();
+    for g in group(&entries, TrimmedLastRun) {
+        for i in g.largest(&entries, TrimmedLastRun) {
+            let (sources, replaced) = g.origins_of(&entries, i, TrimmedLastRun);
+            let mut sources: Vec<Origin> = sources.into_iter().map(origin).collect();
+            sources[1..].sort();
+            let mut replaced: Vec<Origin> = replaced.into_iter().map(origin).collect();
+            replaced.sort();
+            selected.push((i, Provenance { sources, replaced }));
+        }
+    }
+    selected.sort_by(|(i, _), (j, _)| {
+        (entries[*i].desc.as_ref(), &entries[*i].name).cmp(&(entries[*j].desc.as_ref(), &entries[*j].name))
+    });
+    let index = OverlapIndex::new(selected.iter().map(|(i, _)| &entries[*i].desc));
+    let adj: Vec<Vec<usize>> = selected.iter().enumerate().map(|(n, (i, _))| {
+        index.intersecting(&entries[*i].desc).into_iter().filter(|&m| m != n).collect()
+    }).collect();
+
+    let candidate = |n: usize| {
+        let e = &entries[selected[n].0];
+        Candidate { report: &report_names[e.report], report_idx: e.report, name: &e.name, desc: &e.desc }
+    };
+    let mut kept = vec![true; selected.len()];
+    let conflicts = adj.iter().enumerate().flat_map(|(n, ms)| ms.iter().filter(move |&&m| n < m).map(move |&m| (n, m)))
+        .filter_map(|(n, m)| {
+            let (a, b) = (&entries[selected[n].0], &entries[selected[m].0]);
+            let e = add_pair(&a.desc, &b.desc, TrimmedLastRun).err()?;
+            let resolution = resolver.resolve(&candidate(n), &candidate(m));
+            kept[n] &= matches!(resolution, Resolution::KeepBoth | Resolution::KeepA);
+            kept[m] &= matches!(resolution, Resolution::KeepBoth | Resolution::KeepB);
+            Some(MergeConflict {
+                report_a: report_names[a.report].clone(),
+                name_a: a.name.clone(),
+                report_b: report_names[b.report].clone(),
+                name_b: b.name.clone(),
+                kind: ConflictKind::from(&e),
+                overlaps: overlapping_ranges(&a.desc, &b.desc),
+                resolution,
+            })
+        }).collect();
+
+    // Only the descriptions still kept are split between the reports
+    let renumbered: Vec<Option<usize>> = kept.iter().scan(0, |next, &k| {
+        Some(if k { *next += 1; Some(*next - 1) } else { None })
+    }).collect();
+    let adj: Vec<Vec<usize>> = adj.iter().zip(&kept).filter(|(_, &k)| k)
+        .map(|(ms, _)| ms.iter().filter_map(|&m| renumbered[m]).collect())
+        .collect();
+    let colors = color(&adj);
+
+    let num_reports = colors.iter().max().map_or(0, |c| c + 1);
+    let mut outputs: Vec<Vec<(String, FileDescription, Provenance)>> = (0..num_reports).map(|_| Vec::new()).collect();
+    let mut entries: Vec<Option<Entry>> = entries.into_iter().map(Some).collect();
+    let selected = selected.into_iter().zip(kept).filter(|(_, k)| *k).map(|(x, _)| x);
+    for ((i, provenance), c) in selected.zip(colors) {
+        let e = entries[i].take().unwrap();
+        outputs[c].push((e.name, e.desc, provenance));
+    }
+    Merged {
+        reports: outputs.into_iter().map(ReportXml::from_iter).collect(),
+        conflicts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, merge_with, color, overlapping_ranges, ConflictKind};
+    use crate::resolve::{Resolution, LargerFilesize, DropBoth};
+    use crate::report::{Provenance, Origin};
+    use segment_tree::Segment;
+    use crate::file_description::FileDescription;
+    use crate::test_util::fd;
+    use crate::report::ReportXml;
+
+    fn named(reports: Vec<Vec<(String, FileDescription)>>) -> Vec<(String, Vec<(String, FileDescription)>)> {
+        reports.into_iter().enumerate().map(|(i, r)| (format!("r{}", i), r)).collect()
+    }
+
+    fn names(rxs: Vec<ReportXml>) -> Vec<Vec<String>> {
+        rxs.into_iter().map(|rx| rx.iter().map(|x| x.unwrap().0).collect()).collect()
+    }
+
+    #[test]
+    fn test_color() {
+        // A path 0 - 1 - 2 - 3, and an isolated node
+        let adj = vec![vec![1], vec![0, 2], vec![1, 3], vec![2], vec![]];
+        assert_eq!(color(&adj), vec![1, 0, 1, 0, 0]);
+        // A triangle
+        let adj = vec![vec![1, 2], vec![0, 2], vec![0, 1]];
+        let mut colors = color(&adj);
+        colors.sort();
+        assert_eq!(colors, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_merge_minimal() {
+        // Adding these in order to trees, each to the first one without a conflict, needs three
+        // trees: a and d in the first, b in the second and c in the third.
+        let reports = vec![
+            vec![("a".to_owned(), fd(&[(0, 10)])), ("d".to_owned(), fd(&[(20, 10)]))],
+            vec![("b".to_owned(), fd(&[(5, 10)]))],
+            vec![("c".to_owned(), fd(&[(12, 10)]))],
+        ];
+        assert_eq!(names(merge(named(reports)).reports), vec![vec!["b", "d"], vec!["a", "c"]]);
+    }
+
+    #[test]
+    fn test_merge_supersets() {
+        let reports = vec![
+            vec![("short".to_owned(), fd(&[(0, 10)])), ("other".to_owned(), fd(&[(100, 10)]))],
+            vec![("long".to_owned(), fd(&[(0, 10), (50, 10)])), ("other2".to_owned(), fd(&[(100, 10)]))],
+        ];
+        assert_eq!(names(merge(named(reports)).reports), vec![vec!["long", "other"]]);
+    }
+
+    #[test]
+    fn test_merge_order_independent() {
+        let reports = vec![
+            vec![("a".to_owned(), fd(&[(0, 10)])), ("d".to_owned(), fd(&[(20, 10)])), ("e".to_owned(), fd(&[(40, 10)]))],
+            vec![("b".to_owned(), fd(&[(5, 10)])), ("e2".to_owned(), fd(&[(40, 10), (60, 10)]))],
+            vec![("c".to_owned(), fd(&[(12, 10)])), ("f".to_owned(), fd(&[(45, 10)]))],
+        ];
+        let expected = names(merge(named(reports.clone())).reports);
+        let mut reversed = reports.clone();
+        reversed.reverse();
+        assert_eq!(names(merge(named(reversed)).reports), expected);
+        let rotated = vec![reports[1].clone(), reports[2].clone(), reports[0].clone()];
+        assert_eq!(names(merge(named(rotated)).reports), expected);
+    }
+
+    #[test]
+    fn test_overlapping_ranges() {
+        let a = fd(&[(100, 10), (0, 10), (200, 100)]);
+        let b = fd(&[(105, 100), (250, 10)]);
+        assert_eq!(overlapping_ranges(&a, &b), vec![Segment::new(105, 110), Segment::new(200, 205), Segment::new(250, 260)]);
+        assert_eq!(overlapping_ranges(&a, &fd(&[(10, 90)])), vec![]);
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let reports = vec![
+            ("first.xml".to_owned(), vec![("a".to_owned(), fd(&[(0, 10), (50, 10)])), ("d".to_owned(), fd(&[(20, 10)]))]),
+            ("second.xml".to_owned(), vec![("b".to_owned(), fd(&[(5, 10)])), ("e".to_owned(), fd(&[(0, 10), (70, 10)]))]),
+        ];
+        let merged = merge(reports);
+        assert_eq!(merged.reports.len(), 3);
+        let conflicts: Vec<_> = merged.conflicts.iter().map(|c| {
+            (c.report_a.as_str(), c.name_a.as_str(), c.report_b.as_str(), c.name_b.as_str(), c.kind, c.overlaps.clone())
+        }).collect();
+        assert_eq!(conflicts, vec![
+            ("first.xml", "a", "second.xml", "e", ConflictKind::IncompatibleSegmentArrays, vec![Segment::new(0, 10)]),
+            ("first.xml", "a", "second.xml", "b", ConflictKind::IntersectingSegment, vec![Segment::new(5, 10)]),
+            ("second.xml", "e", "second.xml", "b", ConflictKind::IntersectingSegment, vec![Segment::new(5, 10)]),
+        ]);
+    }
+
+    #[test]
+    fn test_merge_provenance() {
+        let reports = vec![
+            ("r1.xml".to_owned(), vec![("short".to_owned(), fd(&[(0, 10), (50, 10)])), ("same".to_owned(), fd(&[(100, 10)]))]),
+            ("r2.xml".to_owned(), vec![("long".to_owned(), fd(&[(0, 10), (50, 10), (70, 10)])), ("same".to_owned(), fd(&[(100, 10)]))]),
+            ("r3.xml".to_owned(), vec![("shorter".to_owned(), fd(&[(0, 10)]))]),
+        ];
+        let merged = merge(reports);
+        assert_eq!(merged.reports.len(), 1);
+        let origin = |report: &str, filename: &str| Origin { report: report.to_owned(), filename: filename.to_owned() };
+        let got: Vec<_> = merged.reports[0].iter_with_provenance().map(|x| { let x = x.unwrap(); (x.0, x.2.unwrap()) }).collect();
+        assert_eq!(got, vec![
+            ("long".to_owned(), Provenance { sources: vec![origin("r2.xml", "long")], replaced: vec![origin("r1.xml", "short"), origin("r3.xml", "shorter")] }),
+            ("same".to_owned(), Provenance { sources: vec![origin("r1.xml", "same"), origin("r2.xml", "same")], replaced: vec![] }),
+        ]);
+    }
+
+    #[test]
+    fn test_merge_trimmed_last_run() {
+        let reports = vec![
+            ("r1.xml".to_owned(), vec![("trimmed".to_owned(), fd(&[(0, 10), (50, 4)]))]),
+            ("r2.xml".to_owned(), vec![("full".to_owned(), fd(&[(0, 10), (50, 10), (70, 10)]))]),
+            ("r3.xml".to_owned(), vec![("cut".to_owned(), fd(&[(0, 10), (50, 10), (70, 6)]))]),
+        ];
+        let merged = merge(reports);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.reports.len(), 1);
+        let got: Vec<_> = merged.reports[0].iter_with_provenance().map(|x| { let x = x.unwrap(); (x.0, x.2.unwrap().replaced.len()) }).collect();
+        assert_eq!(got, vec![("full".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn test_merge_resolvers() {
+        let reports = vec![
+            ("first.xml".to_owned(), vec![("a".to_owned(), fd(&[(0, 10), (50, 10)])), ("d".to_owned(), fd(&[(20, 10)]))]),
+            ("second.xml".to_owned(), vec![("b".to_owned(), fd(&[(5, 10)])), ("e".to_owned(), fd(&[(0, 10), (70, 10)]))]),
+        ];
+        let merged = merge(reports.clone());
+        assert!(merged.conflicts.iter().all(|c| c.resolution == Resolution::KeepBoth));
+
+        // a and e tie, so both are kept; b loses to both
+        let merged = merge_with(reports.clone(), LargerFilesize);
+        assert_eq!(names(merged.reports), vec![vec!["a", "d"], vec!["e"]]);
+        assert_eq!(merged.conflicts.iter().map(|c| c.resolution).collect::<Vec<_>>(),
+                   vec![Resolution::KeepBoth, Resolution::KeepA, Resolution::KeepA]);
+
+        // Every file some other report disagrees about is dropped, b included
+        let merged = merge_with(reports.clone(), DropBoth);
+        assert_eq!(names(merged.reports), vec![vec!["d"]]);
+        assert_eq!(merged.conflicts.iter().map(|c| c.resolution).collect::<Vec<_>>(),
+                   vec![Resolution::DropBoth; 3]);
+
+        // Nor does the order of the reports matter
+        let reversed = reports.into_iter().rev().collect::<Vec<_>>();
+        assert_eq!(names(merge_with(reversed, DropBoth).reports), vec![vec!["d"]]);
+    }
+}
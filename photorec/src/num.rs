@@ -0,0 +1,8 @@
+//
+// Integer helpers not in std as of the minimum supported Rust version.
+//
+
+/// `a / b`, rounded up, without overflowing.
+pub(crate) fn div_ceil(a: u64, b: u64) -> u64 {
+    a / b + (a % b != 0) as u64
+}
@@ -0,0 +1,54 @@
+//
+// An index of many file descriptions by the disk runs they use. Unlike a
+// single SegmentArrayTree, it accepts descriptions which overlap each other.
+//
+use segment_tree::{SegmentArrayTree, AddStatus};
+
+use super::file_description::{ByteRun, FileDescription};
+
+struct Indexed<'a> {
+    idx: usize,
+    desc: &'a FileDescription,
+}
+
+impl<'a> AsRef<[ByteRun]> for Indexed<'a> {
+    fn as_ref(&self) -> &[ByteRun] { self.desc.as_ref() }
+}
+
+pub(crate) struct OverlapIndex<'a> {
+    sats: Vec<SegmentArrayTree<Indexed<'a>, ByteRun>>,
+}
+
+impl<'a> OverlapIndex<'a> {
+    /// Indexes the descriptions by their position in the iterator. Like photorec-merge, we spread
+    /// them over as many trees as needed so that none is left out.
+    pub fn new<T: IntoIterator<Item=&'a FileDescription>>(descs: T) -> Self {
+        let mut sats: Vec<SegmentArrayTree<Indexed, ByteRun>> = Vec::new();
+        for (idx, desc) in descs.into_iter().enumerate() {
+            let mut cur = Some(Indexed { idx, desc });
+            for sat in sats.iter_mut() {
+                match sat.add(cur.take().unwrap()) {
+                    Ok(AddStatus::Added) => { break; }
+                    Ok(AddStatus::AlreadyContained(x)) | Ok(AddStatus::Replaced(x)) | Err((x, _)) => { cur = Some(x); }
+                }
+            }
+            if let Some(x) = cur {
+                let mut sat = SegmentArrayTree::new();
+                sat.add(x).ok();
+                sats.push(sat);
+            }
+        }
+        OverlapIndex { sats }
+    }
+
+    /// Returns the indexes of all the descriptions sharing some disk range with the given one,
+    /// in ascending order.
+    pub fn intersecting(&self, desc: &FileDescription) -> Vec<usize> {
+        let probe = Indexed { idx: 0, desc };
+        let mut idxs: Vec<usize> = self.sats.iter()
+            .flat_map(|sat| sat.all_intersecting(&probe).into_iter().map(move |i| sat.get_by_idx(i).idx))
+            .collect();
+        idxs.sort_unstable();
+        idxs
+    }
+}
@@ -0,0 +1,313 @@
+//
+// Partition tables (MBR, with extended partitions, and GPT), read from an
+// image, for finding which partition a disk position is in.
+//
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use thiserror::Error;
+
+use segment_tree::Segment;
+
+use super::file_description::ByteRun;
+
+/// MBR partition types of extended partitions, holding a chain of logical ones.
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+/// The MBR partition type protecting a GPT.
+const GPT_PROTECTIVE_TYPE: u8 = 0xee;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Bound on the length of a chain of logical partitions, against loops.
+const MAX_LOGICAL: usize = 256;
+/// The boot indicator of an MBR entry: not active, or active.
+const MBR_STATUSES: [u8; 2] = [0x00, 0x80];
+
+#[derive(Error, Debug)]
+pub enum PartitionError {
+    #[error("Error reading: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed partition table: {0}")]
+    Malformed(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// An MBR partition, by its type
+    Mbr(u8),
+    /// A GPT partition, by its type GUID (as stored) and name
+    Gpt { type_guid: [u8; 16], name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// The number of the partition, as Linux numbers them: the entry in the table, from 1; for
+    /// MBR, logical partitions come after the 4 primary ones, from 5.
+    pub number: usize,
+    pub disk: Segment<u64>,
+    pub kind: PartitionKind,
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            PartitionKind::Mbr(t) => write!(f, "{} (type {:#04x})", self.number, t),
+            PartitionKind::Gpt { ref name, .. } if !name.is_empty() => write!(f, "{} ({})", self.number, name),
+            PartitionKind::Gpt { .. } => write!(f, "{}", self.number),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionScheme {
+    Mbr,
+    Gpt,
+}
+
+/// The partitions of an image, sorted by position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub scheme: PartitionScheme,
+    pub partitions: Vec<Partition>,
+}
+
+fn read_sector<R: Read + Seek>(reader: &mut R, pos: u64, sectorsize: u64) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; sectorsize as usize];
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    let mut x = [0; 4];
+    x.copy_from_slice(&buf[at..at + 4]);
+    u32::from_le_bytes(x)
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    let mut x = [0; 8];
+    x.copy_from_slice(&buf[at..at + 8]);
+    u64::from_le_bytes(x)
+}
+
+/// The 4 entries of an MBR or EBR sector, as (type, start sector, sector count); `None` if the
+/// sector isn't one.
+fn mbr_entries(sector: &[u8]) -> Option<[(u8, u64, u64); 4]> {
+    if sector.len() < 512 || sector[510..512] != [0x55, 0xaa] { return None; }
+    let mut entries = [(0, 0, 0); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let e = &sector[446 + 16 * i..446 + 16 * (i + 1)];
+        *entry = (e[4], u32_at(e, 8) as u64, u32_at(e, 12) as u64);
+    }
+    Some(entries)
+}
+
+/// Whether the sector starts like the boot sector of a filesystem (FAT, NTFS, exFAT...): a jump
+/// over the BIOS parameter block, followed by a printable OEM id. These also end in 0x55AA, but
+/// what lies at the place of the MBR entries is boot code.
+fn is_volume_boot_record(sector: &[u8]) -> bool {
+    let jump = (sector[0] == 0xeb && sector[2] == 0x90) || sector[0] == 0xe9;
+    jump && sector[3..11].iter().all(|&b| (0x20..0x7f).contains(&b))
+}
+
+/// Whether the entries of the first sector, of an image of `image_sectors` sectors, make a
+/// plausible MBR: each with a valid boot indicator, and the partitions after the MBR itself,
+/// within the image, and not overlapping one another.
+fn is_valid_mbr(sector: &[u8], entries: &[(u8, u64, u64); 4], image_sectors: u64) -> bool {
+    if (0..4).any(|i| !MBR_STATUSES.contains(&sector[446 + 16 * i])) { return false; }
+    let mut used: Vec<(u64, u64)> = entries.iter().filter(|e| e.0 != 0 && e.2 != 0).map(|e| (e.1, e.1 + e.2)).collect();
+    used.sort_unstable();
+    used.iter().all(|&(start, end)| start > 0 && end <= image_sectors)
+        && used.windows(2).all(|w| w[0].1 <= w[1].0)
+}
+
+fn read_logical<R: Read + Seek>(reader: &mut R, sectorsize: u64, extended_start: u64, partitions: &mut Vec<Partition>)
+    -> Result<(), PartitionError>
+{
+    let mut ebr = extended_start;
+    for number in 5..5 + MAX_LOGICAL {
+        let entries = mbr_entries(&read_sector(reader, ebr * sectorsize, sectorsize)?)
+            .ok_or(PartitionError::Malformed("bad extended boot record"))?;
+        let (t, start, count) = entries[0];
+        if t != 0 && count != 0 {
+            let start = ebr + start;
+            partitions.push(Partition {
+                number,
+                disk: Segment::new(start * sectorsize, (start + count) * sectorsize),
+                kind: PartitionKind::Mbr(t),
+            });
+        }
+        // The next EBR is given relative to the extended partition
+        let (t, next, _) = entries[1];
+        if !EXTENDED_TYPES.contains(&t) || next == 0 { return Ok(()); }
+        ebr = extended_start + next;
+    }
+    Err(PartitionError::Malformed("too many logical partitions"))
+}
+
+fn read_gpt<R: Read + Seek>(reader: &mut R, sectorsize: u64) -> Result<Vec<Partition>, PartitionError> {
+    let header = read_sector(reader, sectorsize, sectorsize)?;
+    if &header[..8] != GPT_SIGNATURE { return Err(PartitionError::Malformed("no GPT header")); }
+    let entries_lba = u64_at(&header, 72);
+    let count = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if entry_size < 128 || count > 1 << 16 { return Err(PartitionError::Malformed("bad GPT entries")); }
+    let mut entries = vec![0; count * entry_size];
+    reader.seek(SeekFrom::Start(entries_lba * sectorsize))?;
+    reader.read_exact(&mut entries)?;
+    let mut partitions = Vec::new();
+    for (i, e) in entries.chunks(entry_size).enumerate() {
+        let mut type_guid = [0; 16];
+        type_guid.copy_from_slice(&e[..16]);
+        if type_guid == [0; 16] { continue; }
+        let (first, last) = (u64_at(e, 32), u64_at(e, 40));
+        if last < first { return Err(PartitionError::Malformed("GPT partition ends before its start")); }
+        let name = e[56..128].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|&c| c != 0).collect::<Vec<_>>();
+        partitions.push(Partition {
+            number: i + 1,
+            disk: Segment::new(first * sectorsize, (last + 1) * sectorsize),
+            kind: PartitionKind::Gpt { type_guid, name: String::from_utf16_lossy(&name) },
+        });
+    }
+    Ok(partitions)
+}
+
+impl PartitionTable {
+    /// Reads the partition table of an image with sectors of `sectorsize`; `None` if it has no
+    /// MBR (e.g. an image of a single partition, whose boot sector isn't taken for one).
+    pub fn read<R: Read + Seek>(reader: &mut R, sectorsize: u64) -> Result<Option<Self>, PartitionError> {
+        let image_sectors = reader.seek(SeekFrom::End(0))?.checked_div(sectorsize).unwrap_or(0);
+        let sector = read_sector(reader, 0, sectorsize.max(512))?;
+        let entries = match mbr_entries(&sector) {
+            Some(_) if is_volume_boot_record(&sector) => return Ok(None),
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let (scheme, mut partitions) = if entries.iter().any(|e| e.0 == GPT_PROTECTIVE_TYPE) {
+            (PartitionScheme::Gpt, read_gpt(reader, sectorsize)?)
+        } else if !is_valid_mbr(&sector, &entries, image_sectors) {
+            return Ok(None);
+        } else {
+            let mut partitions = Vec::new();
+            for (i, &(t, start, count)) in entries.iter().enumerate() {
+                if t == 0 || count == 0 { continue; }
+                if EXTENDED_TYPES.contains(&t) {
+                    read_logical(reader, sectorsize, start, &mut partitions)?;
+                } else {
+                    partitions.push(Partition {
+                        number: i + 1,
+                        disk: Segment::new(start * sectorsize, (start + count) * sectorsize),
+                        kind: PartitionKind::Mbr(t),
+                    });
+                }
+            }
+            (PartitionScheme::Mbr, partitions)
+        };
+        partitions.sort_by_key(|p| (p.disk.start, p.number));
+        Ok(Some(PartitionTable { scheme, partitions }))
+    }
+
+    /// The partition the disk position is in, if any.
+    pub fn containing(&self, pos: u64) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.disk.start <= pos && pos < p.disk.end)
+    }
+
+    /// The partition the whole run is in, if any.
+    pub fn partition_of(&self, br: &ByteRun) -> Option<&Partition> {
+        self.containing(br.disk_pos).filter(|p| br.disk_pos + br.len <= p.disk.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PartitionTable, PartitionScheme, PartitionKind, Partition};
+    use crate::file_description::ByteRun;
+    use segment_tree::Segment;
+    use std::io::Cursor;
+
+    fn put(buf: &mut Vec<u8>, at: usize, data: &[u8]) {
+        if buf.len() < at + data.len() { buf.resize(at + data.len(), 0); }
+        buf[at..at + data.len()].copy_from_slice(data);
+    }
+
+    fn entry(disk: &mut Vec<u8>, sector: usize, i: usize, t: u8, start: u32, count: u32) {
+        let at = sector * 512 + 446 + 16 * i;
+        put(disk, at + 4, &[t]);
+        put(disk, at + 8, &start.to_le_bytes());
+        put(disk, at + 12, &count.to_le_bytes());
+        put(disk, sector * 512 + 510, &[0x55, 0xaa]);
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut disk = vec![0; 512];
+        assert_eq!(PartitionTable::read(&mut Cursor::new(disk.clone()), 512).unwrap(), None);
+        entry(&mut disk, 0, 0, 0x83, 2048, 1000);
+        // Extended at 4096, with logical partitions at 4096+64 and 4096+1000+64
+        entry(&mut disk, 0, 1, 0x0f, 4096, 4000);
+        entry(&mut disk, 4096, 0, 0x07, 64, 500);
+        entry(&mut disk, 4096, 1, 0x05, 1000, 2000);
+        entry(&mut disk, 5096, 0, 0x0b, 64, 900);
+        disk.resize(8192 * 512, 0);
+        let table = PartitionTable::read(&mut Cursor::new(disk), 512).unwrap().unwrap();
+        assert_eq!(table.scheme, PartitionScheme::Mbr);
+        let parts = table.partitions.iter().map(|p| (p.number, p.disk.start / 512, p.disk.end / 512)).collect::<Vec<_>>();
+        assert_eq!(parts, vec![(1, 2048, 3048), (5, 4160, 4660), (6, 5160, 6060)]);
+        assert_eq!(table.partitions[1].to_string(), "5 (type 0x07)");
+
+        assert_eq!(table.containing(4160 * 512).map(|p| p.number), Some(5));
+        assert_eq!(table.containing(4000 * 512), None);
+        let br = ByteRun { file_offset: 0, disk_pos: 3040 * 512, len: 8 * 512 };
+        assert_eq!(table.partition_of(&br).map(|p| p.number), Some(1));
+        let br = ByteRun { file_offset: 0, disk_pos: 3040 * 512, len: 9 * 512 };
+        assert_eq!(table.partition_of(&br), None);
+    }
+
+    #[test]
+    fn test_not_mbr() {
+        let mut disk = vec![0; 8192 * 512];
+        entry(&mut disk, 0, 0, 0x83, 2048, 1000);
+        assert!(PartitionTable::read(&mut Cursor::new(disk.clone()), 512).unwrap().is_some());
+        // The boot sector of a FAT partition, with boot code where the entries would be
+        let mut vbr = disk.clone();
+        put(&mut vbr, 0, b"\xeb\x3c\x90mkfs.fat");
+        assert_eq!(PartitionTable::read(&mut Cursor::new(vbr), 512).unwrap(), None);
+        // A bad boot indicator
+        let mut bad = disk.clone();
+        put(&mut bad, 446, &[0x12]);
+        assert_eq!(PartitionTable::read(&mut Cursor::new(bad), 512).unwrap(), None);
+        // Overlapping partitions
+        let mut bad = disk.clone();
+        entry(&mut bad, 0, 1, 0x83, 3000, 1000);
+        assert_eq!(PartitionTable::read(&mut Cursor::new(bad), 512).unwrap(), None);
+        // A partition past the end of the image
+        let mut bad = disk.clone();
+        entry(&mut bad, 0, 1, 0x83, 8000, 1000);
+        assert_eq!(PartitionTable::read(&mut Cursor::new(bad), 512).unwrap(), None);
+    }
+
+    #[test]
+    fn test_gpt() {
+        let mut disk = vec![0; 512];
+        entry(&mut disk, 0, 0, 0xee, 1, 100000);
+        put(&mut disk, 512, b"EFI PART");
+        put(&mut disk, 512 + 72, &2u64.to_le_bytes());
+        put(&mut disk, 512 + 80, &4u32.to_le_bytes());
+        put(&mut disk, 512 + 84, &128u32.to_le_bytes());
+        let name = "data".encode_utf16().flat_map(|c| c.to_le_bytes()).collect::<Vec<_>>();
+        // The second entry is empty
+        for (i, first, last) in [(0, 4096u64, 8191u64), (2, 2048, 4095)] {
+            let at = 1024 + 128 * i;
+            put(&mut disk, at, &[0xaf; 16]);
+            put(&mut disk, at + 32, &first.to_le_bytes());
+            put(&mut disk, at + 40, &last.to_le_bytes());
+            put(&mut disk, at + 56, &name);
+        }
+        put(&mut disk, 1024 + 128 * 4, &[]);
+        let table = PartitionTable::read(&mut Cursor::new(disk), 512).unwrap().unwrap();
+        assert_eq!(table.scheme, PartitionScheme::Gpt);
+        assert_eq!(table.partitions, vec![
+            Partition { number: 3, disk: Segment::new(2048 * 512, 4096 * 512), kind: PartitionKind::Gpt { type_guid: [0xaf; 16], name: "data".to_owned() } },
+            Partition { number: 1, disk: Segment::new(4096 * 512, 8192 * 512), kind: PartitionKind::Gpt { type_guid: [0xaf; 16], name: "data".to_owned() } },
+        ]);
+        assert_eq!(table.partitions[0].to_string(), "3 (data)");
+    }
+}
@@ -1,26 +1,38 @@
 //
 // A struct for reading (impl Read) from a reader (usu. disk) according to
-// a DescRead descriptor of the mapping from disk to file.
+// a DescRead descriptor of the mapping from disk to file. Holes in the file
+// are filled in.
 //
 use std::io::{Read, Seek, SeekFrom};
 use std::io;
 use std::cmp::min;
 
 use super::file_description::DescRead;
+use super::tolerant::Fill;
 
 
 pub struct ByteRunsReader<R, D> {
     describer: D,
     inner: R,
+    fill: Fill,
 }
 
 impl<R, D> ByteRunsReader<R, D> {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(reader: R, describer: D) -> Self {
         ByteRunsReader {
             describer: describer,
             inner: reader,
+            fill: Fill::Zeros,
         }
     }
+
+    /// What to read in holes of the file; zeros by default. A pattern is repeated from the start
+    /// of each hole.
+    pub fn set_fill(&mut self, fill: Fill) -> &mut Self {
+        self.fill = fill;
+        self
+    }
 }
 
 
@@ -30,9 +42,25 @@ impl<R, D: Seek> Seek for ByteRunsReader<R, D> {
 
 
 impl<R: Read+Seek, D: DescRead> Read for ByteRunsReader<R, D> {
+    #[allow(clippy::bind_instead_of_map)]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let desc = self.describer.desc_read();
-        if desc.len == 0 { return Ok(0); }
+        if desc.len == 0 {
+            let hole = match self.describer.desc_hole() {
+                Some(x) => x,
+                None => { return Ok(0); },
+            };
+            let hole_off = desc.file_offset - hole.start;
+            let n = min(buf.len() as u64, hole.end - desc.file_offset) as usize;
+            match self.fill {
+                Fill::Zeros => buf[..n].iter_mut().for_each(|x| *x = 0),
+                Fill::Pattern(ref p) => for (i, x) in buf[..n].iter_mut().enumerate() {
+                    *x = if p.is_empty() { 0 } else { p[((hole_off + i as u64) % p.len() as u64) as usize] };
+                },
+            }
+            self.describer.adv(n);
+            return Ok(n);
+        }
         let max_len = min(buf.len(), desc.len as usize);
         let buf2 = &mut buf[..max_len];
         self.inner.seek(SeekFrom::Start(desc.disk_pos))
@@ -45,7 +73,7 @@ impl<R: Read+Seek, D: DescRead> Read for ByteRunsReader<R, D> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use super::super::file_description::{ByteRun, FileDescription, FileDescriptionPos};
+    use super::super::file_description::{ByteRun, FileDescription, FileDescriptionPos, Desc};
 
     #[test]
     fn test_file_description_reader_easy() {
@@ -59,6 +87,7 @@ mod tests {
         let mut brr = ByteRunsReader {
             describer: brf,
             inner: reader,
+            fill: Fill::Zeros,
         };
         let mut out = Vec::<u8>::with_capacity(18);
         assert_eq!(brr.read_to_end(&mut out).unwrap(), 18);
@@ -77,6 +106,7 @@ mod tests {
         let mut brr = ByteRunsReader {
             describer: brf,
             inner: reader,
+            fill: Fill::Zeros,
         };
         let mut out = vec![0; 3];
         assert_eq!(brr.read(out.as_mut_slice()).unwrap(), 3);
@@ -88,6 +118,7 @@ mod tests {
 
     
     #[test]
+    #[allow(redundant_semicolons)]
     fn test_file_description_reader_hard() {
         struct LameCursor<T> {
             inner: io::Cursor<T>,
@@ -121,9 +152,28 @@ mod tests {
         let mut brr = ByteRunsReader {
             describer: brf,
             inner: reader,
+            fill: Fill::Zeros,
         };
         let mut out = Vec::<u8>::with_capacity(18);
         assert_eq!(brr.read_to_end(&mut out).unwrap(), 18);
         assert_eq!(out, vec![0, 1, 2, 3, 4, 5, 10, 11, 12, 13, 14, 15, 20, 21, 22, 23, 24, 25]);
     }
+
+    #[test]
+    fn test_file_description_reader_holes() {
+        let br = FileDescription::new_sparse(14, vec![
+            ByteRun { file_offset: 2, disk_pos: 0, len: 4 },
+            ByteRun { file_offset: 9, disk_pos: 20, len: 3 },
+        ]).unwrap();
+        let disk = (1..26).collect::<Vec<u8>>();
+        let mut out = Vec::new();
+        ByteRunsReader::new(io::Cursor::new(&disk), br.at_pos(0)).read_to_end(&mut out).unwrap();
+        assert_eq!(out, vec![0, 0, 1, 2, 3, 4, 0, 0, 0, 21, 22, 23, 0, 0]);
+
+        let mut out = Vec::new();
+        let mut brr = ByteRunsReader::new(io::Cursor::new(&disk), br.at_pos(7));
+        brr.set_fill(Fill::Pattern(b"ab".to_vec()));
+        brr.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"ba\x15\x16\x17ab".to_vec());
+    }
 }
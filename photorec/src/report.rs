@@ -2,18 +2,62 @@
 // A parser, from photorec report.xml to a container of all file descriptions
 // in it, including implementation for "opening" a file so.
 //
-use std::{io::{Read, Write}, num, mem, iter::FromIterator, collections::HashMap};
+use std::{io::{Read, Write}, num, mem, fmt, iter::FromIterator, collections::HashMap};
 
 use thiserror::Error;
 
 use xmltree::{Element, ParseError, XMLNode, Error as WriteError, EmitterConfig};
+use xml::{reader::{EventReader, ParserConfig, XmlEvent}, common::Position, name::OwnedName, attribute::OwnedAttribute, namespace::Namespace};
 
-use super::file_description::{ByteRun, FileDescription, FileDescriptionError};
+use segment_tree::Segment;
+
+use super::file_description::{ByteRun, FileDescription, FileDescriptionError, FileDescriptionBuilder, Policy, Repair};
+
+/// A fileobject of some report, identified by the report's name and its own.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Origin {
+    pub report: String,
+    pub filename: String,
+}
+
+/// Where a fileobject written by a merge came from: the fileobjects it was taken from (several if
+/// identical ones were found), and the smaller versions of it which it replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Provenance {
+    pub sources: Vec<Origin>,
+    pub replaced: Vec<Origin>,
+}
+
+/// Where a fileobject is in its report: its ordinal among the fileobjects, its line and column
+/// in the document (from 1, for parsed reports), and its name if it could be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub index: usize,
+    pub position: Option<(u64, u64)>,
+    pub file_name: Option<String>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fileobject {}", self.index)?;
+        if let Some((line, column)) = self.position {
+            write!(f, " (line {}, column {})", line, column)?;
+        }
+        if let Some(ref name) = self.file_name {
+            write!(f, " named {}", name)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ReportXml {
     image_filename: Option<String>,
     elems: Vec<XMLNode>,
+    /// The line and column of each fileobject, if parsed
+    positions: Vec<(u64, u64)>,
+    /// How the runs of fileobjects are made into FileDescriptions
+    policy: Policy,
 }
 
 type Result<T> = std::result::Result<T, ReportXmlError>;
@@ -22,51 +66,101 @@ type Result<T> = std::result::Result<T, ReportXmlError>;
 pub enum ReportXmlError {
     #[error("Error parsing: {0}")]
     Parse(#[from] ParseError),
-    #[error("Missing field {field_name} in xml")]
-    MissingField { field_name: &'static str },
-    #[error("Missing text in field {field_name} in xml")]
-    MissingText { field_name: String },
-    #[error("Unexpected child of name {field_name} in xml")]
-    BadChildName { expected_name: &'static str, field_name: String },
-    #[error("Missing attr {attr_name} in field {field_name} in xml")]
-    MissingAttr { attr_name: &'static str, field_name: String },
-    #[error("Malformed text in field {field_name} in xml, parse error: {source}")]
-    MalformedText { field_name: String, #[source] source: num::ParseIntError },
-    #[error("Malformed attr {attr_name} in field {field_name} in xml, parse error: {source}")]
-    MalformedAttr { attr_name: &'static str, field_name: String, #[source] source: num::ParseIntError },
-    #[error("File {file_name} has a bad FileDescription: {source}")]
-    BadFileDescription { file_name: String, #[source] source: FileDescriptionError },
+    #[error("Missing field {field_name} in xml{}", at(.location))]
+    MissingField { field_name: &'static str, location: Option<Box<Location>> },
+    #[error("Missing text in field {field_name} in xml{}", at(.location))]
+    MissingText { field_name: String, location: Option<Box<Location>> },
+    #[error("Unexpected child of name {field_name} in xml{}", at(.location))]
+    BadChildName { expected_name: &'static str, field_name: String, location: Option<Box<Location>> },
+    #[error("Missing attr {attr_name} in field {field_name} in xml{}", at(.location))]
+    MissingAttr { attr_name: &'static str, field_name: String, location: Option<Box<Location>> },
+    #[error("Malformed text in field {field_name} in xml{}, parse error: {source}", at(.location))]
+    MalformedText { field_name: String, #[source] source: num::ParseIntError, location: Option<Box<Location>> },
+    #[error("Malformed attr {attr_name} in field {field_name} in xml{}, parse error: {source}", at(.location))]
+    MalformedAttr { attr_name: &'static str, field_name: String, #[source] source: num::ParseIntError, location: Option<Box<Location>> },
+    #[error("File {file_name} has a bad FileDescription{}: {source}", at(.location))]
+    BadFileDescription { file_name: String, #[source] source: FileDescriptionError, location: Option<Box<Location>> },
+    #[error("Offset {offset} in xml can't be moved by {delta}")]
+    BadRebase { offset: u64, delta: i64 },
+}
+
+/// The location of an error, as appended to its message.
+fn at(location: &Option<Box<Location>>) -> String {
+    location.as_ref().map_or_else(String::new, |x| format!(", in {}", x))
+}
+
+impl ReportXmlError {
+    /// Where in the report the error is, if in a fileobject.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ReportXmlError::MissingField { location, .. }
+            | ReportXmlError::MissingText { location, .. }
+            | ReportXmlError::BadChildName { location, .. }
+            | ReportXmlError::MissingAttr { location, .. }
+            | ReportXmlError::MalformedText { location, .. }
+            | ReportXmlError::MalformedAttr { location, .. }
+            | ReportXmlError::BadFileDescription { location, .. } => location.as_deref(),
+            ReportXmlError::Parse(_) | ReportXmlError::BadRebase { .. } => None,
+        }
+    }
+
+    /// Removes the location from the error, e.g. to report it apart from the message.
+    pub fn take_location(&mut self) -> Option<Location> {
+        self.location_mut().and_then(|x| x.take()).map(|x| *x)
+    }
+
+    fn location_mut(&mut self) -> Option<&mut Option<Box<Location>>> {
+        match self {
+            ReportXmlError::MissingField { location, .. }
+            | ReportXmlError::MissingText { location, .. }
+            | ReportXmlError::BadChildName { location, .. }
+            | ReportXmlError::MissingAttr { location, .. }
+            | ReportXmlError::MalformedText { location, .. }
+            | ReportXmlError::MalformedAttr { location, .. }
+            | ReportXmlError::BadFileDescription { location, .. } => Some(location),
+            ReportXmlError::Parse(_) | ReportXmlError::BadRebase { .. } => None,
+        }
+    }
 }
 
 fn get_child<'a>(elem: &'a Element, name: &'static str) -> Result<&'a Element> {
-    elem.get_child(name).ok_or(ReportXmlError::MissingField { field_name: name })
+    elem.get_child(name).ok_or(ReportXmlError::MissingField { field_name: name, location: None })
 }
 
+#[allow(clippy::get_first, clippy::needless_lifetimes)]
 fn get_text<'a>(elem: &'a Element) -> Result<&'a str> {
     elem.children.get(0).and_then(|x| {
         if let XMLNode::Text(s) = x { Some(s.as_ref()) } else { None }
-    }).ok_or(ReportXmlError::MissingText { field_name: elem.name.clone() })
+    }).ok_or(ReportXmlError::MissingText { field_name: elem.name.clone(), location: None })
 }
 
+#[allow(clippy::needless_lifetimes)]
 fn get_number<'a>(elem: &'a Element) -> Result<u64> {
     let x = get_text(elem)?;
-    x.parse::<u64>().map_err(|e| ReportXmlError::MalformedText { field_name: elem.name.clone(), source: e })
+    x.parse::<u64>().map_err(|e| ReportXmlError::MalformedText { field_name: elem.name.clone(), source: e, location: None })
+}
+
+fn get_attr<'a>(elem: &'a Element, name: &'static str) -> Result<&'a str> {
+    elem.attributes.get(name).map(|x| x.as_ref()).ok_or(ReportXmlError::MissingAttr { attr_name: name, field_name: elem.name.clone(), location: None })
 }
 
+#[allow(clippy::needless_lifetimes)]
 fn get_attr_number<'a>(elem: &'a Element, name: &'static str) -> Result<u64> {
-    let x = elem.attributes.get(name).ok_or(ReportXmlError::MissingAttr { attr_name: name, field_name: elem.name.clone() })?;
-    x.parse::<u64>().map_err(|e| ReportXmlError::MalformedAttr { attr_name: name, field_name: elem.name.clone(), source: e })
+    let x = elem.attributes.get(name).ok_or(ReportXmlError::MissingAttr { attr_name: name, field_name: elem.name.clone(), location: None })?;
+    x.parse::<u64>().map_err(|e| ReportXmlError::MalformedAttr { attr_name: name, field_name: elem.name.clone(), source: e, location: None })
 }
 
+#[allow(clippy::needless_lifetimes)]
 fn assert_name<'a>(elem: &'a Element, name: &'static str) -> Result<()> {
     if elem.name != name {
-        Err(ReportXmlError::BadChildName { expected_name: name, field_name: elem.name.clone() })
+        Err(ReportXmlError::BadChildName { expected_name: name, field_name: elem.name.clone(), location: None })
     } else {
         Ok(())
     }
 }
 
-fn to_file_description(elem: &Element) -> Result<(String, FileDescription)> {
+/// The name, size and byte runs of a fileobject, as given.
+fn to_raw_fileobject(elem: &Element) -> Result<(String, u64, Vec<ByteRun>)> {
     let name = get_text(get_child(elem, "filename")?)?.to_owned();
     let size = get_number(get_child(elem, "filesize")?)?;
     let byte_runs = get_child(elem, "byte_runs")?.children.iter()
@@ -78,9 +172,48 @@ fn to_file_description(elem: &Element) -> Result<(String, FileDescription)> {
             let len = get_attr_number(x, "len")?;
             Ok(ByteRun { file_offset, disk_pos, len })
         }).collect::<Result<Vec<ByteRun>>>()?;
-    let file_description = FileDescription::new_allow_missing(size, byte_runs)
-        .map_err(|e| ReportXmlError::BadFileDescription { file_name: name.clone(), source: e })?;
-    Ok((name, file_description))
+    Ok((name, size, byte_runs))
+}
+
+fn to_file_description(elem: &Element, policy: Policy) -> Result<(String, FileDescription, Vec<Repair>)> {
+    let (name, size, byte_runs) = to_raw_fileobject(elem)?;
+    let (file_description, repairs) = FileDescriptionBuilder::new(size).runs(byte_runs).policy(policy).build()
+        .map_err(|e| ReportXmlError::BadFileDescription { file_name: name.clone(), source: e, location: None })?;
+    Ok((name, file_description, repairs))
+}
+
+fn to_provenance(elem: &Element) -> Result<Option<Provenance>> {
+    let elem = match elem.get_child("provenance") {
+        Some(x) => x,
+        None => { return Ok(None); }
+    };
+    let mut provenance = Provenance::default();
+    for x in elem.children.iter().filter_map(|x| { if let XMLNode::Element(e) = x { Some(e) } else { None } }) {
+        let origin = Origin {
+            report: get_attr(x, "report")?.to_owned(),
+            filename: get_attr(x, "filename")?.to_owned(),
+        };
+        match x.name.as_ref() {
+            "source" => provenance.sources.push(origin),
+            "replaced" => provenance.replaced.push(origin),
+            _ => { return Err(ReportXmlError::BadChildName { expected_name: "source", field_name: x.name.clone(), location: None }); }
+        }
+    }
+    Ok(Some(provenance))
+}
+
+fn from_provenance(provenance: &Provenance) -> XMLNode {
+    let origin_elem = |name: &str, origin: &Origin| {
+        let mut e = Element::new(name);
+        e.attributes.insert("report".to_owned(), origin.report.clone());
+        e.attributes.insert("filename".to_owned(), origin.filename.clone());
+        XMLNode::Element(e)
+    };
+    let mut e = Element::new("provenance");
+    e.children = provenance.sources.iter().map(|o| origin_elem("source", o))
+        .chain(provenance.replaced.iter().map(|o| origin_elem("replaced", o)))
+        .collect();
+    XMLNode::Element(e)
 }
 
 fn from_file_description_and_name(name: String, fd: &FileDescription) -> XMLNode {
@@ -108,6 +241,41 @@ fn from_file_description_and_name(name: String, fd: &FileDescription) -> XMLNode
     XMLNode::Element(e)
 }
 
+fn new_element(name: OwnedName, attributes: Vec<OwnedAttribute>, namespace: Namespace) -> Element {
+    let mut elem = Element::new(&name.local_name);
+    elem.prefix = name.prefix;
+    elem.namespace = name.namespace;
+    elem.namespaces = if namespace.is_essentially_empty() { None } else { Some(namespace) };
+    elem.attributes = attributes.into_iter().map(|x| (x.name.local_name, x.value)).collect();
+    elem
+}
+
+/// Reads the children of `elem` up to its end, as `Element::parse` does. If `positions` is given,
+/// records in it the line and column (from 1) of each fileobject child, in the same pass, so the
+/// document is read once however large.
+fn build<R: Read>(reader: &mut EventReader<R>, mut elem: Element, mut positions: Option<&mut Vec<(u64, u64)>>) -> Result<Element> {
+    loop {
+        match reader.next().map_err(ParseError::MalformedXml)? {
+            XmlEvent::EndElement { ref name } if name.local_name == elem.name => { return Ok(elem); },
+            XmlEvent::StartElement { name, attributes, namespace } => {
+                if let Some(ref mut positions) = positions {
+                    if name.local_name == "fileobject" {
+                        let pos = reader.position();
+                        positions.push((pos.row + 1, pos.column + 1));
+                    }
+                }
+                let child = build(reader, new_element(name, attributes, namespace), None)?;
+                elem.children.push(XMLNode::Element(child));
+            },
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => elem.children.push(XMLNode::Text(s)),
+            XmlEvent::Comment(s) => elem.children.push(XMLNode::Comment(s)),
+            XmlEvent::ProcessingInstruction { name, data } => elem.children.push(XMLNode::ProcessingInstruction(name, data)),
+            XmlEvent::Whitespace(_) => {},
+            _ => { return Err(ParseError::CannotParse.into()); },
+        }
+    }
+}
+
 impl ReportXml {
     fn fetch_image_filename(elem: &Element) -> Option<&str> {
         let source = get_child(elem, "source").ok()?;
@@ -116,11 +284,30 @@ impl ReportXml {
     }
 
     pub fn parse<R: Read>(reader: R) -> Result<Self> {
-        let elem = Element::parse(reader)?;
-        Ok(ReportXml {
-            image_filename: Self::fetch_image_filename(&elem).map(|x| x.to_owned()),
-            elems: elem.children,
-        })
+        let mut reader = EventReader::new_with_config(reader, ParserConfig::new().ignore_comments(false));
+        let mut positions = Vec::new();
+        loop {
+            match reader.next().map_err(ParseError::MalformedXml)? {
+                XmlEvent::StartElement { name, attributes, namespace } => {
+                    let elem = build(&mut reader, new_element(name, attributes, namespace), Some(&mut positions))?;
+                    return Ok(ReportXml {
+                        image_filename: Self::fetch_image_filename(&elem).map(|x| x.to_owned()),
+                        elems: elem.children,
+                        positions,
+                        policy: Policy::allow_missing(),
+                    });
+                },
+                XmlEvent::EndDocument => { return Err(ParseError::CannotParse.into()); },
+                _ => {},
+            }
+        }
+    }
+
+    /// Sets how the runs of fileobjects are made into FileDescriptions; by default, as by
+    /// `FileDescription::new_allow_missing`.
+    pub fn set_policy(&mut self, policy: Policy) -> &mut Self {
+        self.policy = policy;
+        self
     }
     
     pub fn image_filename(&self) -> Option<&String> { self.image_filename.as_ref() }
@@ -130,20 +317,166 @@ impl ReportXml {
         image_filename
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item=Result<(String, FileDescription)>> + 'a {
-        self.elems.iter().filter_map(|ref x| {
+    fn source(&self) -> Option<&Element> {
+        self.elems.iter().find_map(|x| match x {
+            XMLNode::Element(e) if e.name == "source" => Some(e),
+            _ => None,
+        })
+    }
+
+    fn source_mut(&mut self) -> Option<&mut Element> {
+        self.elems.iter_mut().find_map(|x| match x {
+            XMLNode::Element(e) if e.name == "source" => Some(e),
+            _ => None,
+        })
+    }
+
+    /// Replaces the source of this report, with the image file name, sector size and volume, by
+    /// a copy of the one of `from`; e.g. for a report of ranges of the image `from` describes.
+    pub fn copy_source(&mut self, from: &ReportXml) {
+        self.elems.retain(|x| !matches!(x, XMLNode::Element(e) if e.name == "source"));
+        if let Some(source) = from.source() {
+            self.elems.insert(0, XMLNode::Element(source.clone()));
+        }
+        self.image_filename = from.image_filename.clone();
+    }
+
+    /// The sector size of the image, if given in the source. A sector size of 0, which nothing
+    /// can be aligned to, is taken as not given.
+    pub fn sectorsize(&self) -> Option<u64> {
+        get_number(get_child(self.source()?, "sectorsize").ok()?).ok().filter(|&x| x != 0)
+    }
+
+    /// The size of the image, if given in the source.
+    pub fn image_size(&self) -> Option<u64> {
+        get_number(get_child(self.source()?, "image_size").ok()?).ok()
+    }
+
+    /// The range of the image carved by photorec, spanning all the byte runs of the volume given
+    /// in the source.
+    pub fn volume(&self) -> Option<Segment<u64>> {
+        let byte_runs = get_child(get_child(self.source()?, "volume").ok()?, "byte_runs").ok()?;
+        let runs = byte_runs.children.iter().filter_map(|x| x.as_element()).map(|e| {
+            let start = get_attr_number(e, "img_offset").ok()?;
+            Some(Segment { start, end: start.checked_add(get_attr_number(e, "len").ok()?)? })
+        }).collect::<Option<Vec<_>>>()?;
+        Some(Segment {
+            start: runs.iter().map(|s| s.start).min()?,
+            end: runs.iter().map(|s| s.end).max()?,
+        })
+    }
+
+    /// The difference between the start of the volume of `to` and of this one, i.e. what to
+    /// rebase this report by so its offsets match those of `to`; for reports carved from the same
+    /// volume, through different devices (e.g. `/dev/sdb` and `/dev/sdb2`). `None` unless both give
+    /// a volume, of the same size.
+    pub fn volume_delta(&self, to: &ReportXml) -> Option<i64> {
+        let (from, to) = (self.volume()?, to.volume()?);
+        if from.end - from.start != to.end - to.start { return None; }
+        Some(to.start as i64 - from.start as i64)
+    }
+
+    /// The byte runs of all fileobjects, and of the volume.
+    fn byte_runs_mut(&mut self) -> impl Iterator<Item=&mut Element> {
+        self.elems.iter_mut().filter_map(|x| match x {
+            XMLNode::Element(e) => match e.name.as_ref() {
+                "fileobject" => e.get_mut_child("byte_runs"),
+                "source" => e.get_mut_child("volume").and_then(|v| v.get_mut_child("byte_runs")),
+                _ => None,
+            },
+            _ => None,
+        }).flat_map(|e| e.children.iter_mut().filter_map(|x| match x {
+            XMLNode::Element(e) if e.name == "byte_run" => Some(e),
+            _ => None,
+        }))
+    }
+
+    /// Moves every byte run, of the fileobjects and of the volume, by `delta` bytes on the disk.
+    /// The runs are then on another image, whose size isn't known, so the image size is removed
+    /// from the source; `copy_source` gives the one of a report of that image. On error, the
+    /// report is left as it was.
+    pub fn rebase(&mut self, delta: i64) -> Result<()> {
+        for run in self.byte_runs_mut() {
+            let offset = get_attr_number(run, "img_offset")?;
+            offset.checked_add_signed(delta).ok_or(ReportXmlError::BadRebase { offset, delta })?;
+        }
+        for run in self.byte_runs_mut() {
+            let offset = get_attr_number(run, "img_offset")?;
+            run.attributes.insert("img_offset".to_owned(), format!("{}", offset.checked_add_signed(delta).unwrap()));
+        }
+        if delta != 0 {
+            if let Some(source) = self.source_mut() { source.take_child("image_size"); }
+        }
+        Ok(())
+    }
+
+    fn fileobjects<'a>(&'a self) -> impl Iterator<Item=&'a Element> + 'a {
+        self.elems.iter().filter_map(|x| {
             if let XMLNode::Element(e) = x {
-                if e.name == "fileobject" { Some(to_file_description(e)) } else { None }
+                if e.name == "fileobject" { Some(e) } else { None }
             } else { None }
         })
     }
 
-    pub fn write<W: Write>(self, writer: W) -> std::result::Result<(), WriteError> {
-        let source_elem = self.image_filename.map(|s| {
-            let mut elem = Element::new("source");
+    /// Records in an error the fileobject of the index it is in, with where it is.
+    fn locate(&self, index: usize, elem: &Element, mut err: ReportXmlError) -> ReportXmlError {
+        let file_name = get_child(elem, "filename").and_then(get_text).ok().map(|x| x.to_owned());
+        if let Some(location) = err.location_mut() {
+            *location = Some(Box::new(Location { index, position: self.positions.get(index).copied(), file_name }));
+        }
+        err
+    }
+
+    /// The file description of each fileobject, by name. Errors give where the fileobject is.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=Result<(String, FileDescription)>> + 'a {
+        self.iter_with_repairs().map(|x| x.map(|(name, fd, _)| (name, fd)))
+    }
+
+    /// Like iter, but also returns the repairs the policy made to the runs of each fileobject.
+    pub fn iter_with_repairs<'a>(&'a self) -> impl Iterator<Item=Result<(String, FileDescription, Vec<Repair>)>> + 'a {
+        self.fileobjects().enumerate().map(move |(i, e)| to_file_description(e, self.policy).map_err(|x| self.locate(i, e, x)))
+    }
+
+    /// The name, size and byte runs of each fileobject, as given, before making a FileDescription
+    /// of them.
+    pub(crate) fn iter_raw<'a>(&'a self) -> impl Iterator<Item=Result<(String, u64, Vec<ByteRun>)>> + 'a {
+        self.fileobjects().enumerate().map(move |(i, e)| to_raw_fileobject(e).map_err(|x| self.locate(i, e, x)))
+    }
+
+    /// Like iter, but also returns the provenance recorded by a merge, if any.
+    pub fn iter_with_provenance<'a>(&'a self) -> impl Iterator<Item=Result<(String, FileDescription, Option<Provenance>)>> + 'a {
+        self.fileobjects().enumerate().map(move |(i, e)| {
+            let fileobject = || -> Result<_> {
+                let (name, fd, _) = to_file_description(e, self.policy)?;
+                Ok((name, fd, to_provenance(e)?))
+            };
+            fileobject().map_err(|x| self.locate(i, e, x))
+        })
+    }
+
+    pub fn write<W: Write>(mut self, writer: W) -> std::result::Result<(), WriteError> {
+        let image_filename_elem = self.image_filename.take().map(|s| {
+            let mut elem = Element::new("image_filename");
             elem.children = vec![XMLNode::Text(s)];
             XMLNode::Element(elem)
         });
+        // A parsed report keeps its source, in which the image file name is replaced
+        let source = self.elems.iter_mut().find_map(|x| match x {
+            XMLNode::Element(e) if e.name == "source" => Some(e),
+            _ => None,
+        });
+        let source_elem = match source {
+            Some(source) => {
+                source.children.retain(|x| !matches!(x, XMLNode::Element(e) if e.name == "image_filename"));
+                if let Some(x) = image_filename_elem { source.children.insert(0, x); }
+                None
+            },
+            None => image_filename_elem.map(|x| {
+                let mut elem = Element::new("source");
+                elem.children = vec![x];
+                XMLNode::Element(elem)
+            }),
+        };
         let mut elem = Element::new("dfxml");
         elem.children = source_elem.into_iter().chain(self.elems).collect();
         elem.write_with_config(writer, EmitterConfig::new().perform_indent(true))
@@ -154,6 +487,8 @@ impl<'a> FromIterator<(String, &'a FileDescription)> for ReportXml {
     fn from_iter<T>(t: T) -> Self where T: IntoIterator<Item=(String, &'a FileDescription)> {
         ReportXml {
             image_filename: None,
+            positions: Vec::new(),
+            policy: Policy::allow_missing(),
             elems: t.into_iter().map(|(s, fd)| from_file_description_and_name(s, fd)).collect(),
         }
     }
@@ -163,16 +498,36 @@ impl FromIterator<(String, FileDescription)> for ReportXml {
     fn from_iter<T>(t: T) -> Self where T: IntoIterator<Item=(String, FileDescription)> {
         ReportXml {
             image_filename: None,
+            positions: Vec::new(),
+            policy: Policy::allow_missing(),
             elems: t.into_iter().map(|(s, fd)| from_file_description_and_name(s, &fd)).collect(),
         }
     }
 }
 
+impl FromIterator<(String, FileDescription, Provenance)> for ReportXml {
+    fn from_iter<T>(t: T) -> Self where T: IntoIterator<Item=(String, FileDescription, Provenance)> {
+        ReportXml {
+            image_filename: None,
+            positions: Vec::new(),
+            policy: Policy::allow_missing(),
+            elems: t.into_iter().map(|(s, fd, provenance)| {
+                let mut node = from_file_description_and_name(s, &fd);
+                if let XMLNode::Element(ref mut e) = node {
+                    e.children.push(from_provenance(&provenance));
+                }
+                node
+            }).collect(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{ReportXml, ReportXmlError};
-    use crate::file_description::{ByteRun, FileDescription, FileDescriptionError};
+    use super::{ReportXml, ReportXmlError, Provenance, Origin, Location};
+    use crate::file_description::{ByteRun, FileDescription, FileDescriptionError, Policy, Repair};
     use std::{iter::FromIterator, io::{Cursor, SeekFrom, Seek}};
+    use segment_tree::Segment;
 
     #[test]
     fn test_report_xml_parse() {
@@ -241,6 +596,9 @@ mod tests {
     </dfxml>"##;
         let mut rx = ReportXml::parse(s.as_bytes()).unwrap();
         assert_eq!(rx.image_filename(), Some(&"/dev/sdb".to_owned()));
+        assert_eq!(rx.sectorsize(), Some(512));
+        assert_eq!(rx.image_size(), Some(123456000000));
+        assert_eq!(rx.volume(), Some(Segment::new(16777216, 16777216 + 123439222784)));
         assert_eq!(rx.set_image_filename(Some("/dev/sdc".to_owned())), Some("/dev/sdb".to_owned()));
         assert_eq!(rx.set_image_filename(None), Some("/dev/sdc".to_owned()));
         let mut rx = rx.iter();
@@ -258,6 +616,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::map_clone)]
     fn test_from_iterator_and_write() {
         let brs1 = vec![ByteRun { file_offset: 0, disk_pos: 1234, len: 50 }, ByteRun { file_offset: 50, disk_pos: 5678, len: 30 }];
         let brs2 = vec![ByteRun { file_offset: 0, disk_pos: 4321, len: 20 }, ByteRun { file_offset: 20, disk_pos: 8765, len: 50 }];
@@ -305,6 +664,143 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_provenance_write_and_parse() {
+        let fd1 = FileDescription::new(80, vec![ByteRun { file_offset: 0, disk_pos: 1234, len: 80 }]).unwrap();
+        let fd2 = FileDescription::new(70, vec![ByteRun { file_offset: 0, disk_pos: 4321, len: 70 }]).unwrap();
+        let origin = |report: &str, filename: &str| Origin { report: report.to_owned(), filename: filename.to_owned() };
+        let provenance = Provenance {
+            sources: vec![origin("r1.xml", "a"), origin("r2.xml", "a2")],
+            replaced: vec![origin("r3.xml", "a3")],
+        };
+        let rx = ReportXml::from_iter(vec![
+            ("a".to_owned(), fd1, provenance.clone()),
+            ("b".to_owned(), fd2, Provenance { sources: vec![origin("r2.xml", "b")], replaced: vec![] }),
+        ]);
+        let mut buf = Cursor::new(Vec::new());
+        rx.write(&mut buf).unwrap();
+        buf.seek(SeekFrom::Start(0)).unwrap();
+        let rx = ReportXml::parse(buf).unwrap();
+        let mut rx_i = rx.iter_with_provenance();
+        let e = rx_i.next().unwrap().unwrap();
+        assert_eq!(e.0, "a");
+        assert_eq!(e.2, Some(provenance));
+        let e = rx_i.next().unwrap().unwrap();
+        assert_eq!(e.0, "b");
+        assert_eq!(e.2.unwrap().sources, vec![origin("r2.xml", "b")]);
+        assert!(rx_i.next().is_none());
+        // Plain iteration ignores the provenance
+        assert_eq!(rx.iter().map(|x| x.unwrap().0).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_provenance_missing_and_errors() {
+        let s = r##"<?xml version='1.0' encoding='UTF-8'?>
+    <dfxml xmloutputversion='1.0'>
+      <fileobject>
+        <filename>f1</filename>
+        <filesize>2</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='1' len='2'/>
+        </byte_runs>
+      </fileobject>
+      <fileobject>
+        <filename>f2</filename>
+        <filesize>2</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='1' len='2'/>
+        </byte_runs>
+        <provenance>
+          <source filename='f2'/>
+        </provenance>
+      </fileobject>
+      <fileobject>
+        <filename>f3</filename>
+        <filesize>2</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='1' len='2'/>
+        </byte_runs>
+        <provenance>
+          <bad_name report='r' filename='f3'/>
+        </provenance>
+      </fileobject>
+    </dfxml>"##;
+        let rx = ReportXml::parse(s.as_bytes()).unwrap();
+        let mut rx = rx.iter_with_provenance();
+        let e = rx.next().unwrap().unwrap();
+        assert_eq!(e.2, None);
+        let e = rx.next().unwrap().err().unwrap();
+        assert_let!(ReportXmlError::MissingAttr { attr_name: a, field_name: ref f, .. } = e, {
+            assert_eq!(a, "report");
+            assert_eq!(*f, "source");
+        });
+        let e = rx.next().unwrap().err().unwrap();
+        assert_let!(ReportXmlError::BadChildName { expected_name: exp, field_name: ref field, .. } = e, {
+            assert_eq!(exp, "source");
+            assert_eq!(*field, "bad_name");
+        });
+    }
+
+    #[test]
+    fn test_rebase_and_write() {
+        let report = |image: &str, volume_offset: u64, file_offset: u64| format!(r##"<?xml version='1.0' encoding='UTF-8'?>
+    <dfxml xmloutputversion='1.0'>
+      <source>
+        <image_filename>{}</image_filename>
+        <sectorsize>512</sectorsize>
+        <image_size>2097152</image_size>
+        <volume>
+          <byte_runs>
+            <byte_run offset='0' img_offset='{}' len='1048576'/>
+          </byte_runs>
+        </volume>
+      </source>
+      <fileobject>
+        <filename>f1.jpg</filename>
+        <filesize>100</filesize>
+        <byte_runs>
+          <byte_run offset='0' img_offset='{}' len='100'/>
+        </byte_runs>
+      </fileobject>
+    </dfxml>"##, image, volume_offset, file_offset);
+        let disk = ReportXml::parse(report("/dev/sdb", 16384, 20480).as_bytes()).unwrap();
+        let mut part = ReportXml::parse(report("/dev/sdb2", 0, 4096).as_bytes()).unwrap();
+        assert_eq!(part.volume_delta(&disk), Some(16384));
+        assert_eq!(disk.volume_delta(&part), Some(-16384));
+        let other = ReportXml::parse(report("/dev/sdb", 0, 0).replace("1048576", "2048").as_bytes()).unwrap();
+        assert_eq!(part.volume_delta(&other), None);
+
+        assert_let!(Err(ReportXmlError::BadRebase { offset: 0, delta: -1 }) = part.rebase(-1));
+        assert_eq!(part.image_size(), Some(2097152));
+        part.rebase(16384).unwrap();
+        // The size of the partition isn't the one of the disk the runs are now on
+        assert_eq!(part.image_size(), None);
+        assert_eq!(part.volume(), disk.volume());
+        part.set_image_filename(disk.image_filename().cloned());
+        let mut buf = Cursor::new(Vec::new());
+        part.write(&mut buf).unwrap();
+        buf.seek(SeekFrom::Start(0)).unwrap();
+        let part = ReportXml::parse(buf).unwrap();
+        // Written with the one source, with the new image
+        assert_eq!(part.image_filename(), Some(&"/dev/sdb".to_owned()));
+        assert_eq!(part.sectorsize(), Some(512));
+        assert_eq!(part.volume(), disk.volume());
+        let (file, disk_file) = (part.iter().next().unwrap().unwrap(), disk.iter().next().unwrap().unwrap());
+        assert_eq!(file.1.as_ref(), disk_file.1.as_ref());
+
+        // A new report takes the whole source of another
+        let mut new: ReportXml = vec![file].into_iter().collect();
+        new.copy_source(&disk);
+        let mut buf = Cursor::new(Vec::new());
+        new.write(&mut buf).unwrap();
+        buf.seek(SeekFrom::Start(0)).unwrap();
+        let new = ReportXml::parse(buf).unwrap();
+        assert_eq!(new.image_filename(), Some(&"/dev/sdb".to_owned()));
+        assert_eq!(new.sectorsize(), Some(512));
+        assert_eq!(new.volume(), disk.volume());
+        assert_eq!(new.iter().count(), 1);
+    }
+
     #[test]
     fn test_report_xml_parse_errors() {
         let s = r##"<?xml version='1.0' encoding='UTF-8'?>
@@ -340,6 +836,56 @@ mod tests {
     }
 
     #[test]
+    fn test_error_location() {
+        let s = r##"<?xml version='1.0' encoding='UTF-8'?>
+<dfxml xmloutputversion='1.0'>
+  <fileobject>
+    <filename>f1</filename>
+    <filesize>2</filesize>
+    <byte_runs>
+      <byte_run offset='0' img_offset='1' len='2'/>
+    </byte_runs>
+  </fileobject>
+  <fileobject>
+    <filename>f2</filename>
+    <byte_runs/>
+  </fileobject>
+    <fileobject><filesize>2</filesize></fileobject>
+</dfxml>"##;
+        let rx = ReportXml::parse(s.as_bytes()).unwrap();
+        let errs = rx.iter().filter_map(|x| x.err()).collect::<Vec<_>>();
+        assert_eq!(errs.len(), 2);
+        assert_eq!(errs[0].location(), Some(&Location { index: 1, position: Some((10, 3)), file_name: Some("f2".to_owned()) }));
+        assert_let!(ReportXmlError::MissingField { field_name: "filesize", .. } = errs[0]);
+        assert_eq!(errs[0].to_string(), "Missing field filesize in xml, in fileobject 1 (line 10, column 3) named f2");
+        assert_eq!(errs[1].location(), Some(&Location { index: 2, position: Some((14, 5)), file_name: None }));
+    }
+
+    #[test]
+    fn test_report_xml_policy() {
+        let s = r##"<?xml version='1.0' encoding='UTF-8'?>
+<dfxml xmloutputversion='1.0'>
+  <fileobject>
+    <filename>f1</filename>
+    <filesize>30</filesize>
+    <byte_runs>
+      <byte_run offset='0' img_offset='100' len='10'/>
+      <byte_run offset='20' img_offset='300' len='10'/>
+    </byte_runs>
+  </fileobject>
+</dfxml>"##;
+        let mut rx = ReportXml::parse(s.as_bytes()).unwrap();
+        let e = rx.iter().next().unwrap().err().unwrap();
+        assert_let!(ReportXmlError::BadFileDescription { source: FileDescriptionError::Gap(_, _), .. } = e);
+        rx.set_policy(Policy::repair());
+        let (name, fd, repairs) = rx.iter_with_repairs().next().unwrap().unwrap();
+        assert_eq!(name, "f1");
+        assert_eq!(fd.missing_ranges(), vec![Segment::new(10, 20)]);
+        assert_eq!(repairs, vec![Repair::ZeroFilled(Segment::new(10, 20))]);
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrow)]
     fn test_report_xml_iter_errors() {
         let s = r##"<?xml version='1.0' encoding='UTF-8'?>
     <dfxml xmloutputversion='1.0'>
@@ -474,67 +1020,67 @@ mod tests {
         let rx = ReportXml::parse(s.as_bytes()).unwrap();
         let mut rx = rx.iter();
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingField { field_name: ref s } = e, {
+        assert_let!(ReportXmlError::MissingField { field_name: ref s, .. } = e, {
             assert_eq!(*s, "filename");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingField { field_name: ref s } = e, {
+        assert_let!(ReportXmlError::MissingField { field_name: ref s, .. } = e, {
             assert_eq!(*s, "filename");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingField { field_name: ref s } = e, {
+        assert_let!(ReportXmlError::MissingField { field_name: ref s, .. } = e, {
             assert_eq!(*s, "filesize");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MalformedText { field_name: ref s, source: _ } = e, {
+        assert_let!(ReportXmlError::MalformedText { field_name: ref s, source: _, .. } = e, {
             assert_eq!(*s, "filesize");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingField { field_name: ref s } = e, {
+        assert_let!(ReportXmlError::MissingField { field_name: ref s, .. } = e, {
             assert_eq!(*s, "byte_runs");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::BadFileDescription { file_name: x, source: e } = e, {
+        assert_let!(ReportXmlError::BadFileDescription { file_name: x, source: e, .. } = e, {
             assert_eq!(x, "f3");
             assert_let!(FileDescriptionError::Empty = e);
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::BadChildName { expected_name: ref exp, field_name: ref field } = e, {
+        assert_let!(ReportXmlError::BadChildName { expected_name: ref exp, field_name: ref field, .. } = e, {
             assert_eq!(*exp, "byte_run");
             assert_eq!(*field, "bad_name");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingAttr { attr_name: ref a, field_name: ref f } = e, {
+        assert_let!(ReportXmlError::MissingAttr { attr_name: ref a, field_name: ref f, .. } = e, {
             assert_eq!(*a, "offset");
             assert_eq!(*f, "byte_run");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MalformedAttr { attr_name: ref a, field_name: ref f, source: _ } = e, {
+        assert_let!(ReportXmlError::MalformedAttr { attr_name: ref a, field_name: ref f, source: _, .. } = e, {
             assert_eq!(*a, "offset");
             assert_eq!(*f, "byte_run");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingAttr { attr_name: ref a, field_name: ref f } = e, {
+        assert_let!(ReportXmlError::MissingAttr { attr_name: ref a, field_name: ref f, .. } = e, {
             assert_eq!(*a, "img_offset");
             assert_eq!(*f, "byte_run");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MalformedAttr { attr_name: ref a, field_name: ref f, source: _ } = e, {
+        assert_let!(ReportXmlError::MalformedAttr { attr_name: ref a, field_name: ref f, source: _, .. } = e, {
             assert_eq!(*a, "img_offset");
             assert_eq!(*f, "byte_run");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MissingAttr { attr_name: ref a, field_name: ref f } = e, {
+        assert_let!(ReportXmlError::MissingAttr { attr_name: ref a, field_name: ref f, .. } = e, {
             assert_eq!(*a, "len");
             assert_eq!(*f, "byte_run");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::MalformedAttr { attr_name: ref a, field_name: ref f, source: _ } = e, {
+        assert_let!(ReportXmlError::MalformedAttr { attr_name: ref a, field_name: ref f, source: _, .. } = e, {
             assert_eq!(*a, "len");
             assert_eq!(*f, "byte_run");
         });
         let e = rx.next().unwrap().err().unwrap();
-        assert_let!(ReportXmlError::BadChildName { expected_name: ref exp, field_name: ref field } = e, {
+        assert_let!(ReportXmlError::BadChildName { expected_name: ref exp, field_name: ref field, .. } = e, {
             assert_eq!(*exp, "byte_run");
             assert_eq!(*field, "bad_name");
         });
@@ -0,0 +1,153 @@
+//
+// Strategies for settling a conflict between two file descriptions while
+// merging, instead of splitting them between output reports.
+//
+use std::fmt;
+
+use super::file_description::FileDescription;
+
+/// One of the two sides of a conflict, as seen by a Resolver.
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate<'a> {
+    /// The name of the report the description came from
+    pub report: &'a str,
+    /// The position of that report among the merged ones
+    pub report_idx: usize,
+    pub name: &'a str,
+    pub desc: &'a FileDescription,
+}
+
+/// What to do with two conflicting file descriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Resolution {
+    /// Keep both, putting them in different output reports
+    KeepBoth,
+    /// Keep only the first one
+    KeepA,
+    /// Keep only the second one
+    KeepB,
+    /// Drop both of them
+    DropBoth,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::KeepBoth => "keep_both",
+            Resolution::KeepA => "keep_a",
+            Resolution::KeepB => "keep_b",
+            Resolution::DropBoth => "drop_both",
+        }
+    }
+
+    /// Keeps the one for which `key` is larger, or both if it is equal.
+    fn prefer_larger<K: Ord, F: Fn(&Candidate<'_>) -> K>(a: &Candidate<'_>, b: &Candidate<'_>, key: F) -> Self {
+        match key(a).cmp(&key(b)) {
+            std::cmp::Ordering::Greater => Resolution::KeepA,
+            std::cmp::Ordering::Less => Resolution::KeepB,
+            std::cmp::Ordering::Equal => Resolution::KeepBoth,
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Decides how to settle each conflict found while merging. It is asked about every conflict, in
+/// a canonical order, including those with a description another conflict already dropped.
+pub trait Resolver {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution;
+}
+
+impl<R: Resolver + ?Sized> Resolver for &mut R {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution { (**self).resolve(a, b) }
+}
+
+impl<R: Resolver + ?Sized> Resolver for Box<R> {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution { (**self).resolve(a, b) }
+}
+
+/// Keeps both descriptions, splitting them between output reports. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Split;
+
+impl Resolver for Split {
+    fn resolve(&mut self, _: &Candidate<'_>, _: &Candidate<'_>) -> Resolution { Resolution::KeepBoth }
+}
+
+/// Keeps the description with the larger filesize, or both on a tie.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargerFilesize;
+
+impl Resolver for LargerFilesize {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution {
+        Resolution::prefer_larger(a, b, |c| c.desc.size())
+    }
+}
+
+/// Keeps the description with fewer byte runs, or both on a tie.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FewerFragments;
+
+impl Resolver for FewerFragments {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution {
+        Resolution::prefer_larger(a, b, |c| std::cmp::Reverse(c.desc.as_ref().len()))
+    }
+}
+
+/// Keeps the description from the report given first, or both if they come from the same one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstReport;
+
+impl Resolver for FirstReport {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution {
+        Resolution::prefer_larger(a, b, |c| std::cmp::Reverse(c.report_idx))
+    }
+}
+
+/// Keeps the description passing the check, e.g. one whose content parses, or both if both or
+/// neither pass.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentCheck<F>(pub F);
+
+impl<F: FnMut(&Candidate<'_>) -> bool> Resolver for ContentCheck<F> {
+    fn resolve(&mut self, a: &Candidate<'_>, b: &Candidate<'_>) -> Resolution {
+        match ((self.0)(a), (self.0)(b)) {
+            (true, false) => Resolution::KeepA,
+            (false, true) => Resolution::KeepB,
+            _ => Resolution::KeepBoth,
+        }
+    }
+}
+
+/// Drops both descriptions, keeping only files no other report disagrees about.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DropBoth;
+
+impl Resolver for DropBoth {
+    fn resolve(&mut self, _: &Candidate<'_>, _: &Candidate<'_>) -> Resolution { Resolution::DropBoth }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Candidate, Resolution, Resolver, Split, LargerFilesize, FewerFragments, FirstReport, ContentCheck, DropBoth};
+    use crate::test_util::fd;
+
+    #[test]
+    fn test_resolvers() {
+        let (big, small) = (fd(&[(0, 10), (50, 10)]), fd(&[(5, 15)]));
+        let a = Candidate { report: "r0", report_idx: 0, name: "a", desc: &big };
+        let b = Candidate { report: "r1", report_idx: 1, name: "b", desc: &small };
+        assert_eq!(Split.resolve(&a, &b), Resolution::KeepBoth);
+        assert_eq!(LargerFilesize.resolve(&a, &b), Resolution::KeepA);
+        assert_eq!(LargerFilesize.resolve(&a, &a), Resolution::KeepBoth);
+        assert_eq!(FewerFragments.resolve(&a, &b), Resolution::KeepB);
+        assert_eq!(FirstReport.resolve(&b, &a), Resolution::KeepB);
+        assert_eq!(ContentCheck(|c: &Candidate<'_>| c.name == "b").resolve(&a, &b), Resolution::KeepB);
+        assert_eq!(ContentCheck(|_: &Candidate<'_>| true).resolve(&a, &b), Resolution::KeepBoth);
+        assert_eq!(DropBoth.resolve(&a, &b), Resolution::DropBoth);
+    }
+}
@@ -0,0 +1,90 @@
+//
+// Extraction of many files at once in disk order, so a rotational disk is read
+// in a single sweep, each region once, however many files use it.
+//
+use std::io::{self, Read, Seek, SeekFrom};
+
+use segment_tree::Segment;
+
+use super::coverage::sweep;
+use super::file_description::FileDescription;
+
+/// A disk region to read, and where to write it: for each file using it, the file's index and
+/// the file offset of the start of the region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub disk: Segment<u64>,
+    pub targets: Vec<(usize, u64)>,
+}
+
+/// Splits the runs of all the files into disjoint regions, sorted by disk position.
+pub fn schedule<'a, I: IntoIterator<Item=&'a FileDescription>>(descs: I) -> Vec<Region> {
+    let runs = descs.into_iter().enumerate()
+        .flat_map(|(idx, desc)| desc.as_ref().iter().map(move |&br| (br, (idx, br))))
+        .collect();
+    sweep(runs).iter().map(|(disk, claims)| {
+        let targets = claims.iter().map(|(idx, br)| (*idx, br.file_offset + (disk.start - br.disk_pos))).collect();
+        Region { disk, targets }
+    }).collect()
+}
+
+/// Reads the regions in order, in chunks of at most `chunk_size` bytes, passing each chunk to
+/// `sink` with the index of every file using it and the file offset it goes to.
+pub fn extract<R, F>(disk: &mut R, regions: &[Region], chunk_size: usize, mut sink: F) -> io::Result<()>
+    where R: Read + Seek, F: FnMut(usize, u64, &[u8]) -> io::Result<()>
+{
+    let mut buf = vec![0; chunk_size];
+    for region in regions {
+        disk.seek(SeekFrom::Start(region.disk.start))?;
+        let mut pos = region.disk.start;
+        while pos < region.disk.end {
+            let len = chunk_size.min((region.disk.end - pos) as usize);
+            let n = disk.read(&mut buf[..len])?;
+            if n == 0 { return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Disk ended inside a region.")); }
+            for &(idx, file_offset) in &region.targets {
+                sink(idx, file_offset + (pos - region.disk.start), &buf[..n])?;
+            }
+            pos += n as u64;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{schedule, extract, Region};
+    use crate::file_description::{ByteRun, FileDescription};
+    use segment_tree::Segment;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_schedule_and_extract() {
+        let descs = vec![
+            FileDescription::new(20, vec![
+                ByteRun { file_offset: 0, disk_pos: 40, len: 10 },
+                ByteRun { file_offset: 10, disk_pos: 10, len: 10 },
+            ]).unwrap(),
+            FileDescription::new(15, vec![ByteRun { file_offset: 0, disk_pos: 45, len: 15 }]).unwrap(),
+        ];
+        let regions = schedule(&descs);
+        assert_eq!(regions, vec![
+            Region { disk: Segment::new(10, 20), targets: vec![(0, 10)] },
+            Region { disk: Segment::new(40, 45), targets: vec![(0, 0)] },
+            Region { disk: Segment::new(45, 50), targets: vec![(0, 5), (1, 0)] },
+            Region { disk: Segment::new(50, 60), targets: vec![(1, 5)] },
+        ]);
+
+        let mut disk = Cursor::new((0..64).collect::<Vec<u8>>());
+        let mut outputs = [vec![0; 20], vec![0; 15]];
+        let mut reads = Vec::new();
+        extract(&mut disk, &regions, 4, |idx, off, data| {
+            reads.push(data[0]);
+            outputs[idx][off as usize..off as usize + data.len()].copy_from_slice(data);
+            Ok(())
+        }).unwrap();
+        assert_eq!(outputs[0], (40..50).chain(10..20).collect::<Vec<u8>>());
+        assert_eq!(outputs[1], (45..60).collect::<Vec<u8>>());
+        // Read in disk order, the shared region once for both files
+        assert_eq!(reads, vec![10, 14, 18, 40, 44, 45, 45, 49, 49, 50, 54, 58]);
+    }
+}
@@ -0,0 +1,264 @@
+//
+// Set operations over several reports, where a fileobject is considered found in
+// a report if it has a fileobject extending it, extended by it or identical to it,
+// as decided by `relate`. Of the files selected, the largest descriptions are
+// kept, leaving out any two conflicting with each other.
+//
+use std::iter::FromIterator;
+
+use segment_tree::Compatibility;
+
+use super::diff::{relate, relate_with, DiffKind};
+use super::file_description::{ByteRun, FileDescription};
+use super::overlap::OverlapIndex;
+use super::report::ReportXml;
+
+pub(crate) struct Entry {
+    pub report: usize,
+    pub name: String,
+    pub desc: FileDescription,
+}
+
+/// A group of fileobjects from all the reports, all describing the same file.
+pub(crate) struct Group {
+    pub members: Vec<usize>,
+}
+
+fn find(parents: &mut [usize], mut x: usize) -> usize {
+    while parents[x] != x {
+        parents[x] = parents[parents[x]];
+        x = parents[x];
+    }
+    x
+}
+
+pub(crate) fn flatten<R, I>(reports: R) -> (usize, Vec<Entry>)
+    where R: IntoIterator<Item=I>, I: IntoIterator<Item=(String, FileDescription)>
+{
+    let mut num_reports = 0;
+    let mut entries = Vec::new();
+    for (report, it) in reports.into_iter().enumerate() {
+        num_reports = report + 1;
+        entries.extend(it.into_iter().map(|(name, desc)| Entry { report, name, desc }));
+    }
+    (num_reports, entries)
+}
+
+/// Groups the entries related under the given compatibility rule.
+pub(crate) fn group<C>(entries: &[Entry], compat: C) -> Vec<Group>
+    where C: Compatibility<ByteRun> + Copy
+{
+    let index = OverlapIndex::new(entries.iter().map(|e| &e.desc));
+    let mut parents: Vec<usize> = (0..entries.len()).collect();
+    for (i, entry) in entries.iter().enumerate() {
+        for j in index.intersecting(&entry.desc) {
+            if j <= i { continue; }
+            match relate_with(&entry.desc, &entries[j].desc, compat) {
+                None | Some(DiffKind::Conflicting) => {}
+                Some(_) => {
+                    let (x, y) = (find(&mut parents, i), find(&mut parents, j));
+                    parents[y] = x;
+                }
+            }
+        }
+    }
+    let mut groups: Vec<Option<Group>> = (0..entries.len()).map(|_| None).collect();
+    for i in 0..entries.len() {
+        let root = find(&mut parents, i);
+        groups[root].get_or_insert_with(|| Group { members: Vec::new() }).members.push(i);
+    }
+    groups.into_iter().flatten().collect()
+}
+
+impl Group {
+    /// The members not extended by any other member, and not identical to one with a smaller name
+    /// (or an earlier one of the same name). Usually there is exactly one, unless two members both
+    /// extend a third one in incompatible ways.
+    pub fn largest<C>(&self, entries: &[Entry], compat: C) -> Vec<usize>
+        where C: Compatibility<ByteRun> + Copy
+    {
+        self.members.iter().cloned().filter(|&i| {
+            !self.members.iter().any(|&j| match relate_with(&entries[i].desc, &entries[j].desc, compat) {
+                Some(DiffKind::ExtendedInB) => true,
+                Some(DiffKind::Identical) => (&entries[j].name, j) < (&entries[i].name, i),
+                _ => false,
+            })
+        }).collect()
+    }
+
+    /// For a member chosen by `largest`, the members identical to it (starting with itself), and
+    /// the members it extends.
+    pub fn origins_of<C>(&self, entries: &[Entry], i: usize, compat: C) -> (Vec<usize>, Vec<usize>)
+        where C: Compatibility<ByteRun> + Copy
+    {
+        let mut sources = vec![i];
+        let mut replaced = Vec::new();
+        for &j in self.members.iter().filter(|&&j| j != i) {
+            match relate_with(&entries[i].desc, &entries[j].desc, compat) {
+                Some(DiffKind::Identical) => sources.push(j),
+                Some(DiffKind::ExtendedInA) => replaced.push(j),
+                _ => {}
+            }
+        }
+        (sources, replaced)
+    }
+}
+
+fn select<R, I, F>(reports: R, pred: F) -> ReportXml
+    where R: IntoIterator<Item=I>, I: IntoIterator<Item=(String, FileDescription)>, F: Fn(usize, &[usize]) -> bool
+{
+    let (num_reports, entries) = flatten(reports);
+    let index = OverlapIndex::new(entries.iter().map(|e| &e.desc));
+    // How each description relates to those sharing disk runs with it, itself included
+    let related: Vec<Vec<(usize, DiffKind)>> = entries.iter().map(|e| {
+        index.intersecting(&e.desc).into_iter()
+            .filter_map(|j| Some((j, relate(&e.desc, &entries[j].desc)?)))
+            .collect()
+    }).collect();
+    // Each description is matched against every report on its own, not through a chain of
+    // descriptions extending one another, which may not agree with each other
+    let candidate: Vec<bool> = related.iter().map(|rel| {
+        let mut found_in: Vec<usize> = rel.iter()
+            .filter(|&&(_, kind)| kind != DiffKind::Conflicting)
+            .map(|&(j, _)| entries[j].report)
+            .collect();
+        found_in.sort_unstable();
+        found_in.dedup();
+        pred(num_reports, &found_in)
+    }).collect();
+    // Conflicting descriptions can't both go in a valid report, so neither is kept
+    let kept: Vec<bool> = related.iter().enumerate().map(|(i, rel)| {
+        candidate[i] && !rel.iter().any(|&(j, kind)| candidate[j] && kind == DiffKind::Conflicting)
+    }).collect();
+    // Of the rest, those not extended by another, nor identical to one with a smaller name (or an
+    // earlier one of the same name)
+    let selected: Vec<usize> = related.iter().enumerate().filter(|&(i, rel)| {
+        kept[i] && !rel.iter().any(|&(j, kind)| kept[j] && match kind {
+            DiffKind::ExtendedInB => true,
+            DiffKind::Identical => (&entries[j].name, j) < (&entries[i].name, i),
+            _ => false,
+        })
+    }).map(|(i, _)| i).collect();
+    let mut entries: Vec<Option<Entry>> = entries.into_iter().map(Some).collect();
+    ReportXml::from_iter(selected.into_iter().map(|i| {
+        let e = entries[i].take().unwrap();
+        (e.name, e.desc)
+    }))
+}
+
+/// All the files found in any of the reports. Two conflicting files are both left out, leaving
+/// only the largest description they both extend, if any; `merge` keeps them in separate reports.
+pub fn union<R, I>(reports: R) -> ReportXml
+    where R: IntoIterator<Item=I>, I: IntoIterator<Item=(String, FileDescription)>
+{
+    select(reports, |_, _| true)
+}
+
+/// The files found in every one of the reports.
+pub fn intersection<R, I>(reports: R) -> ReportXml
+    where R: IntoIterator<Item=I>, I: IntoIterator<Item=(String, FileDescription)>
+{
+    select(reports, |n, found_in| found_in.len() == n)
+}
+
+/// The files found in the first report, but in none of the others.
+pub fn difference<R, I>(reports: R) -> ReportXml
+    where R: IntoIterator<Item=I>, I: IntoIterator<Item=(String, FileDescription)>
+{
+    select(reports, |_, found_in| found_in == [0])
+}
+
+/// The files found in exactly one of the reports. As for `union`, conflicting files are left out.
+pub fn symmetric_difference<R, I>(reports: R) -> ReportXml
+    where R: IntoIterator<Item=I>, I: IntoIterator<Item=(String, FileDescription)>
+{
+    select(reports, |_, found_in| found_in.len() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{relate, DiffKind};
+    use super::{union, intersection, difference, symmetric_difference};
+    use crate::file_description::FileDescription;
+    use crate::test_util::fd;
+    use crate::report::ReportXml;
+
+    fn names(rx: ReportXml) -> Vec<String> {
+        rx.iter().map(|x| x.unwrap().0).collect()
+    }
+
+    fn reports() -> Vec<Vec<(String, FileDescription)>> {
+        vec![
+            vec![
+                ("a_same".to_owned(), fd(&[(100, 10)])),
+                ("a_short".to_owned(), fd(&[(200, 10)])),
+                ("a_only".to_owned(), fd(&[(300, 10)])),
+                ("a_conflict".to_owned(), fd(&[(400, 10)])),
+            ],
+            vec![
+                ("b_same".to_owned(), fd(&[(100, 10)])),
+                ("b_long".to_owned(), fd(&[(200, 10), (250, 10)])),
+                ("b_only".to_owned(), fd(&[(500, 10)])),
+                ("b_conflict".to_owned(), fd(&[(405, 10)])),
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_union() {
+        assert_eq!(names(union(reports())), vec!["a_same", "a_only", "b_long", "b_only"]);
+    }
+
+    #[test]
+    fn test_intersection() {
+        assert_eq!(names(intersection(reports())), vec!["a_same", "b_long"]);
+    }
+
+    #[test]
+    fn test_difference() {
+        assert_eq!(names(difference(reports())), vec!["a_only", "a_conflict"]);
+        let mut r = reports();
+        r.reverse();
+        assert_eq!(names(difference(r)), vec!["b_only", "b_conflict"]);
+    }
+
+    #[test]
+    fn test_symmetric_difference() {
+        assert_eq!(names(symmetric_difference(reports())), vec!["a_only", "b_only"]);
+    }
+
+    #[test]
+    fn test_incompatible_extensions() {
+        let r = vec![
+            vec![("base".to_owned(), fd(&[(100, 10)]))],
+            vec![("ext1".to_owned(), fd(&[(100, 10), (200, 10)]))],
+            vec![("ext2".to_owned(), fd(&[(100, 10), (300, 10)]))],
+        ];
+        // ext1 and ext2 conflict, and neither is found in every report
+        assert_eq!(names(union(r.clone())), vec!["base"]);
+        assert_eq!(names(intersection(r.clone())), vec!["base"]);
+        assert!(names(symmetric_difference(r)).is_empty());
+    }
+
+    #[test]
+    fn test_conflicting_candidates() {
+        // Both x and y are found in both reports, through a prefix disjoint from the other, but
+        // they conflict on their second run, so only the prefixes are kept
+        let r = vec![
+            vec![("x".to_owned(), fd(&[(100, 10), (300, 10)])), ("p".to_owned(), fd(&[(200, 10)]))],
+            vec![("y".to_owned(), fd(&[(200, 10), (305, 10)])), ("a".to_owned(), fd(&[(100, 10)]))],
+        ];
+        assert_eq!(names(intersection(r.clone())), vec!["p", "a"]);
+        assert_eq!(names(union(r)), vec!["p", "a"]);
+    }
+
+    #[test]
+    fn test_trimmed_last_run_agrees_with_relate() {
+        // A last run trimmed to a shorter length is a different file here, as it is for `diff`
+        let (a, b) = (fd(&[(100, 10), (200, 5)]), fd(&[(100, 10), (200, 10)]));
+        assert_eq!(relate(&a, &b), Some(DiffKind::Conflicting));
+        let r = vec![vec![("a".to_owned(), a)], vec![("b".to_owned(), b)]];
+        assert_eq!(names(difference(r.clone())), vec!["a"]);
+        assert!(names(intersection(r)).is_empty());
+    }
+}
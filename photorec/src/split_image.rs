@@ -0,0 +1,176 @@
+//
+// A raw image split in several segment files (image.001, image.002, ... or
+// image.aa, image.ab, ...), read as a single device.
+//
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// The segments of a split image, read (impl Read + Seek) as their concatenation. Reads crossing
+/// from one segment to the next are served from both. Can be used as the inner reader of a
+/// ByteRunsReader.
+pub struct SplitImage<R> {
+    segments: Vec<R>,
+    /// The position in the image of the start of each segment, and of the end of the image
+    starts: Vec<u64>,
+    pos: u64,
+}
+
+/// The extension of the segment after one with extension `ext`: numbers are incremented keeping
+/// their width (001 -> 002, 999 -> 1000), and two lowercase letters counted in base 26, as named
+/// by `split` (aa -> ab, az -> ba). `None` for any other extension, which is then of an unsplit
+/// image.
+fn next_extension(ext: &str) -> Option<String> {
+    if ext.is_empty() { return None; }
+    if ext.bytes().all(|b| b.is_ascii_digit()) {
+        let n = ext.parse::<u64>().ok()? + 1;
+        return Some(format!("{:0width$}", n, width = ext.len()));
+    }
+    if ext.len() == 2 && ext.bytes().all(|b| b.is_ascii_lowercase()) {
+        let mut next = ext.as_bytes().to_vec();
+        for b in next.iter_mut().rev() {
+            if *b == b'z' {
+                *b = b'a';
+            } else {
+                *b += 1;
+                return String::from_utf8(next).ok();
+            }
+        }
+        // Ran out of letters
+        return None;
+    }
+    None
+}
+
+/// The paths of the segments of the image starting at `first`, i.e. `first` and the following
+/// segments which exist. A series of letters must start at `aa`, so that e.g. `disk.dd` isn't
+/// taken for a segment followed by `disk.de`.
+pub fn segment_paths(first: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![first.to_path_buf()];
+    let ext = first.extension().and_then(OsStr::to_str).unwrap_or("");
+    if ext.bytes().any(|b| b.is_ascii_lowercase()) && ext != "aa" {
+        return paths;
+    }
+    loop {
+        let next = paths.last().unwrap().extension().and_then(OsStr::to_str)
+            .and_then(next_extension)
+            .map(|ext| first.with_extension(ext));
+        match next {
+            Some(p) if p.exists() => paths.push(p),
+            _ => break,
+        }
+    }
+    paths
+}
+
+impl SplitImage<File> {
+    /// Opens the image starting at segment `first`, finding the other segments by their names.
+    pub fn open<P: AsRef<Path>>(first: P) -> io::Result<Self> {
+        let segments = segment_paths(first.as_ref()).iter().map(File::open).collect::<io::Result<_>>()?;
+        SplitImage::new(segments)
+    }
+}
+
+impl<R: Seek> SplitImage<R> {
+    pub fn new(mut segments: Vec<R>) -> io::Result<Self> {
+        let mut starts = vec![0];
+        for seg in segments.iter_mut() {
+            let len = seg.seek(SeekFrom::End(0))?;
+            starts.push(starts.last().unwrap() + len);
+        }
+        Ok(SplitImage { segments, starts, pos: 0 })
+    }
+}
+
+impl<R> SplitImage<R> {
+    pub fn len(&self) -> u64 { *self.starts.last().unwrap() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    pub fn into_inner(self) -> Vec<R> { self.segments }
+}
+
+impl<R: Read + Seek> Read for SplitImage<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut done = 0;
+        while done < buf.len() && self.pos < self.len() {
+            // The last segment starting at or before pos; empty segments are skipped over
+            let idx = self.starts.partition_point(|&s| s <= self.pos) - 1;
+            let seg_end = self.starts[idx + 1];
+            let len = (buf.len() - done).min((seg_end - self.pos) as usize);
+            let seg = &mut self.segments[idx];
+            seg.seek(SeekFrom::Start(self.pos - self.starts[idx]))?;
+            let n = seg.read(&mut buf[done..done + len])?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Image segment shorter than its size."));
+            }
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+}
+
+impl<R> Seek for SplitImage<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x),
+            SeekFrom::End(x) => self.len().checked_add_signed(x),
+        };
+        self.pos = new.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bad seek pos."))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SplitImage, next_extension};
+    use crate::file_description::{ByteRun, FileDescription, Desc};
+    use crate::reader::ByteRunsReader;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    #[test]
+    fn test_next_extension() {
+        assert_eq!(next_extension("001").as_deref(), Some("002"));
+        assert_eq!(next_extension("009").as_deref(), Some("010"));
+        assert_eq!(next_extension("999").as_deref(), Some("1000"));
+        assert_eq!(next_extension("aa").as_deref(), Some("ab"));
+        assert_eq!(next_extension("az").as_deref(), Some("ba"));
+        assert_eq!(next_extension("zz"), None);
+        assert_eq!(next_extension("img"), None);
+        assert_eq!(next_extension("a"), None);
+        assert_eq!(next_extension("E01"), None);
+        assert_eq!(next_extension(""), None);
+    }
+
+    #[test]
+    fn test_split_image() {
+        let segments = vec![
+            Cursor::new((0..10).collect::<Vec<u8>>()),
+            Cursor::new(vec![]),
+            Cursor::new((10..16).collect()),
+            Cursor::new((16..30).collect()),
+        ];
+        let mut img = SplitImage::new(segments).unwrap();
+        assert_eq!(img.len(), 30);
+        let mut buf = [0; 12];
+        img.seek(SeekFrom::Start(8)).unwrap();
+        // Crosses the empty segment and the next one
+        assert_eq!(img.read(&mut buf).unwrap(), 12);
+        assert_eq!(buf, [8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+        img.seek(SeekFrom::End(-3)).unwrap();
+        assert_eq!(img.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], &[27, 28, 29]);
+        assert_eq!(img.read(&mut buf).unwrap(), 0);
+
+        let desc = FileDescription::new(12, vec![
+            ByteRun { file_offset: 0, disk_pos: 14, len: 4 },
+            ByteRun { file_offset: 4, disk_pos: 6, len: 8 },
+        ]).unwrap();
+        let mut out = Vec::new();
+        ByteRunsReader::new(&mut img, desc.at_pos(0)).read_to_end(&mut out).unwrap();
+        assert_eq!(out, vec![14, 15, 16, 17, 6, 7, 8, 9, 10, 11, 12, 13]);
+    }
+}
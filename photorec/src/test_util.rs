@@ -0,0 +1,15 @@
+//
+// Helpers shared by the unit tests.
+//
+use super::file_description::{ByteRun, FileDescription};
+
+/// A complete file made of the given `(disk_pos, len)` runs, in file order.
+pub(crate) fn fd(runs: &[(u64, u64)]) -> FileDescription {
+    let mut off = 0;
+    let runs: Vec<ByteRun> = runs.iter().map(|&(disk_pos, len)| {
+        let br = ByteRun { file_offset: off, disk_pos, len };
+        off += len;
+        br
+    }).collect();
+    FileDescription::new(off, runs).unwrap()
+}
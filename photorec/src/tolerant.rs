@@ -0,0 +1,219 @@
+//
+// A reader for failing devices: read errors are retried, and sectors which
+// still can't be read are filled in and recorded as damaged.
+//
+use std::io::{Read, Seek, SeekFrom};
+use std::io;
+use std::thread::sleep;
+use std::time::Duration;
+
+use segment_tree::Segment;
+
+/// What to put in place of a sector which couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fill {
+    Zeros,
+    /// The pattern repeated from the start of each sector, so damage is easy to spot
+    Pattern(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadPolicy {
+    /// How many times to retry a failed read
+    pub retries: u32,
+    /// How long to wait before the first retry; doubled before each of the following ones
+    pub backoff: Duration,
+    pub sectorsize: u64,
+    pub fill: Fill,
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        ReadPolicy { retries: 3, backoff: Duration::from_millis(100), sectorsize: 512, fill: Fill::Zeros }
+    }
+}
+
+/// Wraps a reader (usu. disk) so that reads don't fail: a read which keeps failing is done again
+/// sector by sector, and the sectors which still can't be read are filled according to the
+/// policy. The disk ranges filled so are recorded. Can be used as the inner reader of a
+/// ByteRunsReader.
+pub struct TolerantReader<R> {
+    inner: R,
+    policy: ReadPolicy,
+    pos: u64,
+    damaged: Vec<Segment<u64>>,
+}
+
+impl<R> TolerantReader<R> {
+    pub fn new(inner: R, policy: ReadPolicy) -> Self {
+        TolerantReader { inner, policy, pos: 0, damaged: Vec::new() }
+    }
+
+    /// The disk ranges filled in since last called, in the order they were read.
+    pub fn take_damaged(&mut self) -> Vec<Segment<u64>> { std::mem::take(&mut self.damaged) }
+
+    pub fn into_inner(self) -> R { self.inner }
+}
+
+impl<R: Read + Seek> TolerantReader<R> {
+    fn read_with_retries(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut backoff = self.policy.backoff;
+        let mut tries = 0;
+        loop {
+            let res = self.inner.seek(SeekFrom::Start(pos)).and_then(|_| self.inner.read(buf));
+            match res {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) if tries < self.policy.retries => {
+                    tries += 1;
+                    sleep(backoff);
+                    backoff *= 2;
+                }
+                _ => return res,
+            }
+        }
+    }
+
+    /// Fills in the disk range at `pos` which couldn't be read, and records it.
+    fn fill(&mut self, pos: u64, buf: &mut [u8]) {
+        match self.policy.fill {
+            Fill::Zeros => buf.iter_mut().for_each(|x| *x = 0),
+            Fill::Pattern(ref p) => {
+                let sector_off = (pos % self.policy.sectorsize) as usize;
+                for (i, x) in buf.iter_mut().enumerate() {
+                    *x = if p.is_empty() { 0 } else { p[(sector_off + i) % p.len()] };
+                }
+            },
+        }
+        self.damaged.push(Segment::new(pos, pos + buf.len() as u64));
+    }
+}
+
+impl<R: Read + Seek> Read for TolerantReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() { return Ok(0); }
+        let n = match self.read_with_retries(self.pos, buf) {
+            Ok(n) => n,
+            Err(_) => {
+                // A read of the whole buffer may have failed on any of its sectors, so the rest of
+                // it is read sector by sector, and only those which fail by themselves are filled
+                // in; retrying the whole buffer again would retry every bad sector in it each time
+                let mut done = 0;
+                while done < buf.len() {
+                    let pos = self.pos + done as u64;
+                    let sector_end = (pos / self.policy.sectorsize + 1) * self.policy.sectorsize;
+                    let len = (buf.len() - done).min((sector_end - pos) as usize);
+                    let narrowed = &mut buf[done..done + len];
+                    match self.read_with_retries(pos, narrowed) {
+                        Ok(0) => break,
+                        Ok(n) => { done += n; },
+                        Err(_) => {
+                            self.fill(pos, narrowed);
+                            done += len;
+                        },
+                    }
+                }
+                done
+            },
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for TolerantReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(x) => x,
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bad seek pos."))?,
+            SeekFrom::End(_) => self.inner.seek(pos)?,
+        };
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TolerantReader, ReadPolicy, Fill};
+    use crate::file_description::{ByteRun, FileDescription, Desc};
+    use crate::reader::ByteRunsReader;
+    use segment_tree::Segment;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom};
+    use std::time::Duration;
+
+    /// A disk of 8-byte sectors, where reads touching a bad sector fail, and the first reads
+    /// touching a flaky one fail too. Counts the reads made.
+    struct FailingDisk {
+        inner: Cursor<Vec<u8>>,
+        bad: Vec<u64>,
+        flaky: Vec<(u64, u32)>,
+        reads: u32,
+    }
+
+    impl Read for FailingDisk {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            let (start, end) = (self.inner.position() / 8, (self.inner.position() + buf.len() as u64 - 1) / 8);
+            if self.bad.iter().any(|s| (start..=end).contains(s)) {
+                return Err(io::Error::other("bad sector"));
+            }
+            for (s, failures) in self.flaky.iter_mut() {
+                if (start..=end).contains(s) && *failures > 0 {
+                    *failures -= 1;
+                    return Err(io::Error::other("flaky sector"));
+                }
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for FailingDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> { self.inner.seek(pos) }
+    }
+
+    fn policy(fill: Fill) -> ReadPolicy {
+        ReadPolicy { retries: 2, backoff: Duration::from_millis(1), sectorsize: 8, fill }
+    }
+
+    #[test]
+    fn test_tolerant_read() {
+        let disk = FailingDisk { inner: Cursor::new((1..=64).collect()), bad: vec![2, 5], flaky: vec![(3, 2), (6, 2)], reads: 0 };
+        let mut tr = TolerantReader::new(disk, policy(Fill::Pattern(b"BAD".to_vec())));
+        let mut out = Vec::new();
+        tr.read_to_end(&mut out).unwrap();
+        let mut expected: Vec<u8> = (1..=64).collect();
+        expected[16..24].copy_from_slice(b"BADBADBA");
+        expected[40..48].copy_from_slice(b"BADBADBA");
+        // The flaky sectors are read in the end, by retrying them by themselves
+        assert_eq!(out, expected);
+        assert_eq!(tr.take_damaged(), vec![Segment::new(16, 24), Segment::new(40, 48)]);
+        assert_eq!(tr.take_damaged(), vec![]);
+    }
+
+    #[test]
+    fn test_tolerant_byte_runs_reader() {
+        let disk = FailingDisk { inner: Cursor::new((0..64).collect()), bad: vec![3], flaky: vec![], reads: 0 };
+        let mut tr = TolerantReader::new(disk, policy(Fill::Zeros));
+        let desc = FileDescription::new(20, vec![
+            ByteRun { file_offset: 0, disk_pos: 4, len: 8 },
+            ByteRun { file_offset: 8, disk_pos: 20, len: 12 },
+        ]).unwrap();
+        let mut out = Vec::new();
+        ByteRunsReader::new(&mut tr, desc.at_pos(0)).read_to_end(&mut out).unwrap();
+        assert_eq!(out, vec![4, 5, 6, 7, 8, 9, 10, 11, 20, 21, 22, 23, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let damaged = tr.take_damaged();
+        assert_eq!(damaged, vec![Segment::new(24, 32)]);
+        assert_eq!(desc.file_ranges(&damaged[0]), vec![Segment::new(12, 20)]);
+    }
+
+    #[test]
+    fn test_tolerant_read_retries() {
+        let disk = FailingDisk { inner: Cursor::new((0..=255).collect()), bad: vec![29], flaky: vec![], reads: 0 };
+        let mut tr = TolerantReader::new(disk, policy(Fill::Zeros));
+        let mut out = vec![0; 256];
+        tr.read_exact(&mut out).unwrap();
+        assert_eq!(tr.take_damaged(), vec![Segment::new(232, 240)]);
+        // The whole buffer and the bad sector are tried 3 times each, every other sector once
+        assert_eq!(tr.into_inner().reads, 3 + 31 + 3);
+    }
+}
@@ -0,0 +1,510 @@
+//
+// Virtual machine disks (qcow2, VHD and VMDK), which store the disk in blocks
+// located by tables, leaving out the blocks never written. VHDX disks are
+// recognised, but refused.
+//
+use std::io::{self, Read, Seek, SeekFrom};
+
+use flate2::read::DeflateDecoder;
+use thiserror::Error;
+
+use super::num::div_ceil;
+
+const QCOW2_MAGIC: &[u8; 4] = b"QFI\xfb";
+const VMDK_MAGIC: &[u8; 4] = b"KDMV";
+const VHD_COOKIE: &[u8; 8] = b"conectix";
+const VHD_SPARSE_COOKIE: &[u8; 8] = b"cxsparse";
+const VHDX_SIGNATURE: &[u8; 8] = b"vhdxfile";
+/// The qcow2 incompatible features not changing how data is read: the dirty and corrupt bits
+const QCOW2_KNOWN_INCOMPATIBLE: u64 = 0b11;
+/// The (arbitrary) block size used for reading fixed VHDs, which have a single block
+const VHD_FIXED_BLOCK_SIZE: u64 = 1 << 20;
+
+#[derive(Error, Debug)]
+pub enum VirtualDiskError {
+    #[error("Error reading: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a qcow2, VHD or VMDK disk")]
+    UnknownFormat,
+    #[error("Unsupported {0}")]
+    Unsupported(&'static str),
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskFormat {
+    Qcow2,
+    Vhd,
+    /// Not supported, but recognised so that it isn't read as a raw disk
+    Vhdx,
+    Vmdk,
+}
+
+/// Where the data of a block is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mapping {
+    Zeros,
+    At(u64),
+    /// Deflated (qcow2 only), from the offset for at most the length
+    Deflated(u64, u64),
+}
+
+enum Layout {
+    Qcow2 { cluster_bits: u32, l1: Vec<u64> },
+    VhdFixed,
+    VhdDynamic { block_size: u64, bitmap_size: u64, bat: Vec<u32> },
+    Vmdk { grain_size: u64, gtes_per_gt: u64, gd: Vec<u32> },
+}
+
+/// A virtual disk, read (impl Read + Seek) as the disk it holds. Blocks not allocated in the
+/// file read as zeros. Can be used as the inner reader of a ByteRunsReader.
+pub struct VirtualDisk<R> {
+    inner: R,
+    format: DiskFormat,
+    layout: Layout,
+    size: u64,
+    pos: u64,
+    /// The last second-level table read (qcow2 L2 table, or VMDK grain table), by its offset
+    table: Option<(u64, Vec<u64>)>,
+    /// The last compressed block read, by its index
+    inflated: Option<(u64, Vec<u8>)>,
+}
+
+fn read_at<R: Read + Seek>(inner: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    inner.seek(SeekFrom::Start(offset))?;
+    inner.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// The table of `count` entries of `entry_size` bytes at the offset. Its size comes from a header
+/// which may be corrupt, so it must fit in the file before anything is allocated for it.
+fn read_table<R: Read + Seek>(inner: &mut R, offset: u64, count: u64, entry_size: u64, what: &'static str) -> Result<Vec<u8>, VirtualDiskError> {
+    let file_len = inner.seek(SeekFrom::End(0))?;
+    let len = count.checked_mul(entry_size)
+        .filter(|&len| offset.checked_add(len).is_some_and(|end| end <= file_len))
+        .ok_or(VirtualDiskError::Malformed(what))?;
+    Ok(read_at(inner, offset, len as usize)?)
+}
+
+fn be_u32(buf: &[u8], at: usize) -> u32 {
+    let mut x = [0; 4];
+    x.copy_from_slice(&buf[at..at + 4]);
+    u32::from_be_bytes(x)
+}
+
+fn be_u64(buf: &[u8], at: usize) -> u64 {
+    let mut x = [0; 8];
+    x.copy_from_slice(&buf[at..at + 8]);
+    u64::from_be_bytes(x)
+}
+
+fn le_u32(buf: &[u8], at: usize) -> u32 {
+    let mut x = [0; 4];
+    x.copy_from_slice(&buf[at..at + 4]);
+    u32::from_le_bytes(x)
+}
+
+fn le_u64(buf: &[u8], at: usize) -> u64 {
+    let mut x = [0; 8];
+    x.copy_from_slice(&buf[at..at + 8]);
+    u64::from_le_bytes(x)
+}
+
+/// The format of the virtual disk, if it's one we know.
+pub fn detect_format<R: Read + Seek>(inner: &mut R) -> io::Result<Option<DiskFormat>> {
+    let len = inner.seek(SeekFrom::End(0))?;
+    if len < 512 { return Ok(None); }
+    let magic = read_at(inner, 0, 8)?;
+    if &magic[..4] == QCOW2_MAGIC { return Ok(Some(DiskFormat::Qcow2)); }
+    if &magic[..4] == VMDK_MAGIC { return Ok(Some(DiskFormat::Vmdk)); }
+    if magic == VHDX_SIGNATURE { return Ok(Some(DiskFormat::Vhdx)); }
+    if read_at(inner, len - 512, 8)? == VHD_COOKIE { return Ok(Some(DiskFormat::Vhd)); }
+    Ok(None)
+}
+
+fn open_qcow2<R: Read + Seek>(inner: &mut R) -> Result<(Layout, u64), VirtualDiskError> {
+    let header = read_at(inner, 0, 104)?;
+    let version = be_u32(&header, 4);
+    if version != 2 && version != 3 { return Err(VirtualDiskError::Unsupported("qcow2 version")); }
+    if be_u64(&header, 8) != 0 { return Err(VirtualDiskError::Unsupported("qcow2 backing file")); }
+    if be_u32(&header, 32) != 0 { return Err(VirtualDiskError::Unsupported("qcow2 encryption")); }
+    // Any other, e.g. external data files, compression other than deflate, or extended L2 entries
+    if version == 3 && be_u64(&header, 72) & !QCOW2_KNOWN_INCOMPATIBLE != 0 {
+        return Err(VirtualDiskError::Unsupported("qcow2 features"));
+    }
+    let cluster_bits = be_u32(&header, 20);
+    if !(9..=21).contains(&cluster_bits) { return Err(VirtualDiskError::Malformed("qcow2 cluster size")); }
+    let size = be_u64(&header, 24);
+    let l1_size = be_u32(&header, 36) as u64;
+    let l1 = read_table(inner, be_u64(&header, 40), l1_size, 8, "qcow2 L1 table")?;
+    let l1 = l1.chunks(8).map(|e| be_u64(e, 0)).collect();
+    Ok((Layout::Qcow2 { cluster_bits, l1 }, size))
+}
+
+fn open_vhd<R: Read + Seek>(inner: &mut R) -> Result<(Layout, u64), VirtualDiskError> {
+    let len = inner.seek(SeekFrom::End(0))?;
+    let footer = read_at(inner, len - 512, 512)?;
+    let size = be_u64(&footer, 48);
+    match be_u32(&footer, 60) {
+        2 => Ok((Layout::VhdFixed, size)),
+        3 => {
+            let header = read_at(inner, be_u64(&footer, 16), 1024)?;
+            if &header[..8] != VHD_SPARSE_COOKIE { return Err(VirtualDiskError::Malformed("VHD dynamic header")); }
+            let entries = be_u32(&header, 28) as u64;
+            let block_size = be_u32(&header, 32) as u64;
+            if block_size == 0 || block_size % 512 != 0 { return Err(VirtualDiskError::Malformed("VHD block size")); }
+            // A bit for each sector of the block, padded to a sector
+            let bitmap_size = div_ceil(div_ceil(block_size / 512, 8), 512) * 512;
+            let bat = read_table(inner, be_u64(&header, 16), entries, 4, "VHD block table")?;
+            let bat = bat.chunks(4).map(|e| be_u32(e, 0)).collect();
+            Ok((Layout::VhdDynamic { block_size, bitmap_size, bat }, size))
+        },
+        4 => Err(VirtualDiskError::Unsupported("VHD differencing disk")),
+        _ => Err(VirtualDiskError::Malformed("VHD disk type")),
+    }
+}
+
+fn open_vmdk<R: Read + Seek>(inner: &mut R) -> Result<(Layout, u64), VirtualDiskError> {
+    let header = read_at(inner, 0, 79)?;
+    let flags = le_u32(&header, 8);
+    // Compressed grains, as in streamOptimized disks
+    if flags & 0x10000 != 0 || u16::from_le_bytes([header[77], header[78]]) != 0 {
+        return Err(VirtualDiskError::Unsupported("VMDK compression"));
+    }
+    let capacity = le_u64(&header, 12);
+    let grain_size = le_u64(&header, 20);
+    let gtes_per_gt = le_u32(&header, 44) as u64;
+    let gd_offset = le_u64(&header, 56);
+    if gd_offset == u64::MAX { return Err(VirtualDiskError::Unsupported("VMDK grain directory at end")); }
+    if grain_size == 0 || gtes_per_gt == 0 || grain_size.checked_mul(512).is_none() {
+        return Err(VirtualDiskError::Malformed("VMDK header"));
+    }
+    let size = capacity.checked_mul(512).ok_or(VirtualDiskError::Malformed("VMDK capacity"))?;
+    // Grain tables are read as needed, so their size is checked here
+    let file_len = inner.seek(SeekFrom::End(0))?;
+    if gtes_per_gt * 4 > file_len { return Err(VirtualDiskError::Malformed("VMDK grain table size")); }
+    let gd_entries = div_ceil(div_ceil(capacity, grain_size), gtes_per_gt);
+    let gd_offset = gd_offset.checked_mul(512).ok_or(VirtualDiskError::Malformed("VMDK grain directory"))?;
+    let gd = read_table(inner, gd_offset, gd_entries, 4, "VMDK grain directory")?;
+    let gd = gd.chunks(4).map(|e| le_u32(e, 0)).collect();
+    Ok((Layout::Vmdk { grain_size, gtes_per_gt, gd }, size))
+}
+
+impl<R: Read + Seek> VirtualDisk<R> {
+    /// The virtual disk stored in `inner`, of the format found in its headers.
+    pub fn new(mut inner: R) -> Result<Self, VirtualDiskError> {
+        let format = detect_format(&mut inner)?.ok_or(VirtualDiskError::UnknownFormat)?;
+        let (layout, size) = match format {
+            DiskFormat::Qcow2 => open_qcow2(&mut inner)?,
+            DiskFormat::Vhd => open_vhd(&mut inner)?,
+            DiskFormat::Vhdx => return Err(VirtualDiskError::Unsupported("VHDX disk")),
+            DiskFormat::Vmdk => open_vmdk(&mut inner)?,
+        };
+        Ok(VirtualDisk { inner, format, layout, size, pos: 0, table: None, inflated: None })
+    }
+
+    fn block_size(&self) -> u64 {
+        match self.layout {
+            Layout::Qcow2 { cluster_bits, .. } => 1 << cluster_bits,
+            Layout::VhdFixed => VHD_FIXED_BLOCK_SIZE,
+            Layout::VhdDynamic { block_size, .. } => block_size,
+            Layout::Vmdk { grain_size, .. } => grain_size * 512,
+        }
+    }
+
+    /// The entries of the second-level table at the offset, of `count` entries of 4 or 8 bytes.
+    fn table(&mut self, offset: u64, count: usize, wide: bool) -> io::Result<&[u64]> {
+        if self.table.as_ref().map(|(o, _)| *o) != Some(offset) {
+            let raw = read_at(&mut self.inner, offset, count * if wide { 8 } else { 4 })?;
+            let entries = if wide {
+                raw.chunks(8).map(|e| be_u64(e, 0)).collect()
+            } else {
+                raw.chunks(4).map(|e| le_u32(e, 0) as u64).collect()
+            };
+            self.table = Some((offset, entries));
+        }
+        Ok(&self.table.as_ref().unwrap().1)
+    }
+
+    fn locate(&mut self, block: u64) -> io::Result<Mapping> {
+        let block_size = self.block_size();
+        let (offset, count, wide, idx) = match self.layout {
+            Layout::VhdFixed => return Ok(Mapping::At(block * block_size)),
+            Layout::VhdDynamic { bitmap_size, ref bat, .. } => {
+                return Ok(match bat.get(block as usize) {
+                    None | Some(&0xffff_ffff) => Mapping::Zeros,
+                    Some(&sector) => Mapping::At(sector as u64 * 512 + bitmap_size),
+                });
+            },
+            Layout::Qcow2 { cluster_bits, ref l1 } => {
+                let l2_entries = 1u64 << (cluster_bits - 3);
+                let l2_offset = match l1.get((block / l2_entries) as usize) {
+                    Some(e) => e & 0x00ff_ffff_ffff_fe00,
+                    None => 0,
+                };
+                if l2_offset == 0 { return Ok(Mapping::Zeros); }
+                (l2_offset, l2_entries as usize, true, (block % l2_entries) as usize)
+            },
+            Layout::Vmdk { gtes_per_gt, ref gd, .. } => {
+                let gt_sector = gd.get((block / gtes_per_gt) as usize).copied().unwrap_or(0);
+                if gt_sector == 0 { return Ok(Mapping::Zeros); }
+                (gt_sector as u64 * 512, gtes_per_gt as usize, false, (block % gtes_per_gt) as usize)
+            },
+        };
+        let entry = self.table(offset, count, wide)?[idx];
+        Ok(match self.layout {
+            Layout::Qcow2 { cluster_bits, .. } => {
+                if entry & (1 << 62) != 0 {
+                    let x = 62 - (cluster_bits - 8);
+                    let host_offset = entry & ((1 << x) - 1);
+                    let sectors = ((entry >> x) & ((1 << (cluster_bits - 8)) - 1)) + 1;
+                    Mapping::Deflated(host_offset, sectors * 512 - (host_offset & 511))
+                } else if entry & 1 != 0 || entry & 0x00ff_ffff_ffff_fe00 == 0 {
+                    // Explicitly zero, or unallocated
+                    Mapping::Zeros
+                } else {
+                    Mapping::At(entry & 0x00ff_ffff_ffff_fe00)
+                }
+            },
+            // 1 is a grain explicitly zeroed
+            _ => if entry <= 1 { Mapping::Zeros } else { Mapping::At(entry * 512) },
+        })
+    }
+
+    fn inflate(&mut self, block: u64, offset: u64, len: u64) -> io::Result<&[u8]> {
+        if self.inflated.as_ref().map(|(b, _)| *b) != Some(block) {
+            let block_size = self.block_size();
+            self.inner.seek(SeekFrom::Start(offset))?;
+            let mut data = Vec::with_capacity(block_size as usize);
+            DeflateDecoder::new((&mut self.inner).take(len)).take(block_size).read_to_end(&mut data)?;
+            if (data.len() as u64) < block_size {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Compressed cluster {} is too short", block)));
+            }
+            self.inflated = Some((block, data));
+        }
+        Ok(&self.inflated.as_ref().unwrap().1)
+    }
+}
+
+impl<R> VirtualDisk<R> {
+    pub fn format(&self) -> DiskFormat { self.format }
+
+    /// The size of the disk.
+    pub fn len(&self) -> u64 { self.size }
+
+    pub fn is_empty(&self) -> bool { self.size == 0 }
+
+    pub fn into_inner(self) -> R { self.inner }
+}
+
+impl<R: Read + Seek> Read for VirtualDisk<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block_size = self.block_size();
+        let mut done = 0;
+        while done < buf.len() && self.pos < self.size {
+            let (block, off) = (self.pos / block_size, self.pos % block_size);
+            let n = ((buf.len() - done) as u64).min(block_size - off).min(self.size - self.pos) as usize;
+            let out = &mut buf[done..done + n];
+            match self.locate(block)? {
+                Mapping::Zeros => out.iter_mut().for_each(|x| *x = 0),
+                Mapping::At(x) => {
+                    self.inner.seek(SeekFrom::Start(x + off))?;
+                    self.inner.read_exact(out)?;
+                },
+                Mapping::Deflated(x, len) => {
+                    out.copy_from_slice(&self.inflate(block, x, len)?[off as usize..off as usize + n]);
+                },
+            }
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+}
+
+impl<R> Seek for VirtualDisk<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x),
+            SeekFrom::End(x) => self.size.checked_add_signed(x),
+        };
+        self.pos = new.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bad seek pos."))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VirtualDisk, VirtualDiskError, DiskFormat, detect_format};
+    use crate::file_description::{ByteRun, FileDescription, Desc};
+    use crate::reader::ByteRunsReader;
+    use crate::num::div_ceil;
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+    fn put(buf: &mut Vec<u8>, at: usize, data: &[u8]) {
+        if buf.len() < at + data.len() { buf.resize(at + data.len(), 0); }
+        buf[at..at + data.len()].copy_from_slice(data);
+    }
+
+    /// The disk the images below hold: 4 blocks of 512 bytes, the second never written, the
+    /// fourth all zeros.
+    fn disk() -> Vec<u8> {
+        let mut disk = (0..1536).map(|x| (x % 251) as u8 + 1).collect::<Vec<u8>>();
+        disk[512..1024].iter_mut().for_each(|x| *x = 0);
+        disk.resize(2048, 0);
+        disk
+    }
+
+    fn read_all(img: Vec<u8>) -> (DiskFormat, Vec<u8>) {
+        let mut vd = VirtualDisk::new(Cursor::new(img)).unwrap();
+        let mut out = Vec::new();
+        vd.read_to_end(&mut out).unwrap();
+        (vd.format(), out)
+    }
+
+    #[test]
+    fn test_qcow2() {
+        let disk = disk();
+        // Clusters of 512 bytes; header, L1 table, L2 table, then data clusters
+        let mut img = Vec::new();
+        put(&mut img, 0, b"QFI\xfb");
+        put(&mut img, 4, &3u32.to_be_bytes());
+        put(&mut img, 20, &9u32.to_be_bytes());
+        put(&mut img, 24, &2048u64.to_be_bytes());
+        put(&mut img, 36, &1u32.to_be_bytes());
+        put(&mut img, 40, &512u64.to_be_bytes());
+        put(&mut img, 512, &1024u64.to_be_bytes());
+        put(&mut img, 1024, &1536u64.to_be_bytes());
+        put(&mut img, 1536, &disk[..512]);
+        // The third cluster compressed, the fourth explicitly zero
+        let mut enc = DeflateEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(&disk[1024..1536]).unwrap();
+        let deflated = enc.finish().unwrap();
+        let x = 62 - (9 - 8);
+        let sectors = div_ceil(deflated.len() as u64, 512);
+        put(&mut img, 1024 + 16, &((1u64 << 62) | ((sectors - 1) << x) | 2048).to_be_bytes());
+        put(&mut img, 1024 + 24, &1u64.to_be_bytes());
+        put(&mut img, 2048, &deflated);
+        img.resize(2048 + sectors as usize * 512, 0);
+        assert_eq!(read_all(img.clone()), (DiskFormat::Qcow2, disk));
+
+        // Dirty is fine, extended L2 entries are not
+        put(&mut img, 72, &1u64.to_be_bytes());
+        assert!(VirtualDisk::new(Cursor::new(img.clone())).is_ok());
+        put(&mut img, 72, &(1u64 << 4).to_be_bytes());
+        let res = VirtualDisk::new(Cursor::new(img.clone())).err();
+        assert_let!(Some(VirtualDiskError::Unsupported("qcow2 features")) = res);
+
+        // An L1 table past the end of the file
+        put(&mut img, 72, &0u64.to_be_bytes());
+        put(&mut img, 36, &u32::MAX.to_be_bytes());
+        let res = VirtualDisk::new(Cursor::new(img)).err();
+        assert_let!(Some(VirtualDiskError::Malformed("qcow2 L1 table")) = res);
+    }
+
+    #[test]
+    fn test_vhd() {
+        let disk = disk();
+        let mut footer = vec![0; 512];
+        put(&mut footer, 0, b"conectix");
+        put(&mut footer, 48, &2048u64.to_be_bytes());
+        // Fixed: the disk followed by the footer
+        put(&mut footer, 60, &2u32.to_be_bytes());
+        let mut img = disk.clone();
+        img.extend_from_slice(&footer);
+        assert_eq!(read_all(img), (DiskFormat::Vhd, disk.clone()));
+
+        // Dynamic, with blocks of 1024 bytes: footer copy, dynamic header, BAT, then blocks, each
+        // a sector of bitmap and the data
+        put(&mut footer, 60, &3u32.to_be_bytes());
+        put(&mut footer, 16, &512u64.to_be_bytes());
+        let mut img = footer.clone();
+        put(&mut img, 512, b"cxsparse");
+        put(&mut img, 512 + 16, &1536u64.to_be_bytes());
+        put(&mut img, 512 + 28, &2u32.to_be_bytes());
+        put(&mut img, 512 + 32, &1024u32.to_be_bytes());
+        put(&mut img, 1536, &4u32.to_be_bytes());
+        put(&mut img, 1540, &0xffff_ffffu32.to_be_bytes());
+        put(&mut img, 2048 + 512, &disk[..1024]);
+        let len = img.len();
+        put(&mut img, len, &footer);
+        let mut expected = disk[..1024].to_vec();
+        expected.resize(2048, 0);
+        assert_eq!(read_all(img.clone()), (DiskFormat::Vhd, expected));
+
+        // A block table past the end of the file
+        put(&mut img, 512 + 28, &u32::MAX.to_be_bytes());
+        let res = VirtualDisk::new(Cursor::new(img)).err();
+        assert_let!(Some(VirtualDiskError::Malformed("VHD block table")) = res);
+    }
+
+    #[test]
+    fn test_vmdk() {
+        let disk = disk();
+        // Grains of 1 sector, 2 to a grain table; header, grain directory, grain tables, grains
+        let mut img = Vec::new();
+        put(&mut img, 0, b"KDMV");
+        put(&mut img, 4, &1u32.to_le_bytes());
+        put(&mut img, 12, &4u64.to_le_bytes());
+        put(&mut img, 20, &1u64.to_le_bytes());
+        put(&mut img, 44, &2u32.to_le_bytes());
+        put(&mut img, 56, &1u64.to_le_bytes());
+        put(&mut img, 512, &2u32.to_le_bytes());
+        put(&mut img, 516, &3u32.to_le_bytes());
+        put(&mut img, 1024, &4u32.to_le_bytes());
+        put(&mut img, 1536, &5u32.to_le_bytes());
+        put(&mut img, 1540, &1u32.to_le_bytes());
+        put(&mut img, 2048, &disk[..512]);
+        put(&mut img, 2560, &disk[1024..1536]);
+        let (format, out) = read_all(img.clone());
+        assert_eq!((format, out), (DiskFormat::Vmdk, disk.clone()));
+
+        // Sizes in the header which overflow, or don't fit in the file
+        let mut bad = img.clone();
+        put(&mut bad, 12, &u64::MAX.to_le_bytes());
+        let res = VirtualDisk::new(Cursor::new(bad)).err();
+        assert_let!(Some(VirtualDiskError::Malformed("VMDK capacity")) = res);
+        let mut bad = img.clone();
+        put(&mut bad, 20, &(1u64 << 60).to_le_bytes());
+        let res = VirtualDisk::new(Cursor::new(bad)).err();
+        assert_let!(Some(VirtualDiskError::Malformed("VMDK header")) = res);
+        let mut bad = img.clone();
+        put(&mut bad, 44, &u32::MAX.to_le_bytes());
+        let res = VirtualDisk::new(Cursor::new(bad)).err();
+        assert_let!(Some(VirtualDiskError::Malformed("VMDK grain table size")) = res);
+        let mut bad = img.clone();
+        put(&mut bad, 12, &(1u64 << 50).to_le_bytes());
+        let res = VirtualDisk::new(Cursor::new(bad)).err();
+        assert_let!(Some(VirtualDiskError::Malformed("VMDK grain directory")) = res);
+
+        let mut vd = VirtualDisk::new(Cursor::new(img)).unwrap();
+        vd.seek(SeekFrom::Start(500)).unwrap();
+        let mut buf = [0xff; 24];
+        vd.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &disk[500..524]);
+        let desc = FileDescription::new(8, vec![
+            ByteRun { file_offset: 0, disk_pos: 1030, len: 4 },
+            ByteRun { file_offset: 4, disk_pos: 10, len: 4 },
+        ]).unwrap();
+        let mut out = Vec::new();
+        ByteRunsReader::new(&mut vd, desc.at_pos(0)).read_to_end(&mut out).unwrap();
+        assert_eq!(out, [&disk[1030..1034], &disk[10..14]].concat());
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(detect_format(&mut Cursor::new(disk())).unwrap(), None);
+        let res = VirtualDisk::new(Cursor::new(disk())).err();
+        assert_let!(Some(VirtualDiskError::UnknownFormat) = res);
+
+        let mut vhdx = disk();
+        put(&mut vhdx, 0, b"vhdxfile");
+        assert_eq!(detect_format(&mut Cursor::new(vhdx.clone())).unwrap(), Some(DiskFormat::Vhdx));
+        let res = VirtualDisk::new(Cursor::new(vhdx)).err();
+        assert_let!(Some(VirtualDiskError::Unsupported("VHDX disk")) = res);
+    }
+}
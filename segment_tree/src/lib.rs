@@ -1,8 +1,8 @@
 mod segment_tree;
 mod segment_array_tree;
 
-pub use crate::segment_tree::{Segment, SegmentTree, SegmentTreeError, Entry, VacantEntry, OccupiedEntry};
-pub use crate::segment_array_tree::{SegmentArrayTree, SegmentArrayTreeError, AddStatus};
+pub use crate::segment_tree::{Segment, SegmentTree, SegmentTreeError, Entry, VacantEntry, OccupiedEntry, Iter};
+pub use crate::segment_array_tree::{SegmentArrayTree, SegmentArrayTreeError, AddStatus, Compatibility, Exact};
 
 #[cfg(test)]
 #[macro_use]
@@ -1,6 +1,7 @@
 
 use super::segment_tree::{Segment, SegmentTree, SegmentTreeError, Entry};
 
+use std::cmp::Ordering;
 use std::marker::PhantomData;
 use std::{slice, vec};
 use std::iter::IntoIterator;
@@ -17,10 +18,29 @@ pub enum SegmentArrayTreeError {
     IncompatibleSegmentArrays(usize),
 }
 
+/// Decides whether two segment arrays sharing segments describe the same thing, one of them
+/// extending the other.
+pub trait Compatibility<I> {
+    /// Returns `Some(Greater)` if `new` strictly extends `old`, `Some(Equal)` or `Some(Less)` if
+    /// `old` already contains `new`, and None if they are incompatible.
+    fn compare(&self, new: &[I], old: &[I]) -> Option<Ordering>;
+}
+
+/// The default rule: the shorter segment array must be an exact prefix of the longer one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Exact;
+
+impl<I: PartialEq> Compatibility<I> for Exact {
+    fn compare(&self, new: &[I], old: &[I]) -> Option<Ordering> {
+        if new.iter().zip(old).all(|(x, y)| x == y) { Some(new.len().cmp(&old.len())) } else { None }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct SegmentArrayTree<M, I> {
+pub struct SegmentArrayTree<M, I, C = Exact> {
     tree: SegmentTree<u64, usize>,
     segment_arrays: Vec<M>,
+    compatibility: C,
     _phantom: PhantomData<*const I>,
 }
 
@@ -34,15 +54,24 @@ pub enum AddStatus<M> {
     Replaced(M),
 }
 
-impl<M, I> SegmentArrayTree<M, I> where M: AsRef<[I]>, for<'a> &'a I: Into<Segment<u64>> + Eq {
-    pub fn new() -> Self {
+impl<M, I> SegmentArrayTree<M, I> where M: AsRef<[I]>, I: PartialEq, for<'a> &'a I: Into<Segment<u64>> + Eq {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self { Self::with_compatibility(Exact) }
+}
+
+impl<M, I, C> SegmentArrayTree<M, I, C> where M: AsRef<[I]>, C: Compatibility<I>, for<'a> &'a I: Into<Segment<u64>> + Eq {
+    /// Creates an empty tree, which decides whether intersecting segment arrays extend one
+    /// another by the given rule.
+    pub fn with_compatibility(compatibility: C) -> Self {
         SegmentArrayTree {
             tree: SegmentTree::new(),
             segment_arrays: Vec::new(),
+            compatibility,
             _phantom: PhantomData,
         }
     }
 
+    #[allow(clippy::into_iter_on_ref)]
     pub fn search_intersecting(&mut self, seg_arr: &M) -> Result<Option<usize>, SegmentArrayTreeError> {
         let mut idx: Option<usize> = None;
         for seg in seg_arr.as_ref().into_iter().map(|s| s.into()) {
@@ -61,8 +90,42 @@ impl<M, I> SegmentArrayTree<M, I> where M: AsRef<[I]>, for<'a> &'a I: Into<Segme
         Ok(idx)
     }
 
+    /// Returns the indexes of all the segment arrays in the tree which intersect any segment of
+    /// the given one, in ascending order.
+    pub fn all_intersecting(&self, seg_arr: &M) -> Vec<usize> {
+        let mut idxs: Vec<usize> = seg_arr.as_ref().iter()
+            .flat_map(|s| self.tree.iter_intersecting(&s.into()).map(|(_, idx)| *idx))
+            .collect();
+        idxs.sort_unstable();
+        idxs.dedup();
+        idxs
+    }
+
+    /// Like `search_intersecting`, but a segment only partially intersecting one of the segment
+    /// array in the tree doesn't fail the search, so the compatibility rule gets to decide. Returns
+    /// the index found, and whether any of the segments only partially intersected it.
+    fn search_candidate(&self, seg_arr: &M) -> Result<Option<(usize, bool)>, SegmentArrayTreeError> {
+        let mut idx: Option<usize> = None;
+        let mut partial = false;
+        for seg in seg_arr.as_ref().iter().map(|s| s.into()) {
+            for (found, &x) in self.tree.iter_intersecting(&seg) {
+                let is_partial = found != seg;
+                let y = *idx.get_or_insert(x);
+                if y != x {
+                    // An intersection is reported before an overlap, like `search_intersecting`
+                    if partial { return Err(SegmentArrayTreeError::IntersectingSegment(y)); }
+                    if is_partial { return Err(SegmentArrayTreeError::IntersectingSegment(x)); }
+                    return Err(SegmentArrayTreeError::OverlappingSegmentArrays(y, x));
+                }
+                partial |= is_partial;
+            }
+        }
+        Ok(idx.map(|x| (x, partial)))
+    }
+
+    #[allow(clippy::into_iter_on_ref)]
     pub fn add(&mut self, mut seg_arr: M) -> Result<AddStatus<M>, (M, SegmentArrayTreeError)>  {
-        let idx = match self.search_intersecting(&seg_arr) {
+        let idx = match self.search_candidate(&seg_arr) {
             Ok(idx) => idx,
             Err(e) => { return Err((seg_arr, e)); },
         };
@@ -72,18 +135,21 @@ impl<M, I> SegmentArrayTree<M, I> where M: AsRef<[I]>, for<'a> &'a I: Into<Segme
                 self.segment_arrays.push(seg_arr);
                 (Some(self.segment_arrays.len() - 1), AddStatus::Added)
             }
-            Some(x) => {
-                // Make sure they are really compatible
-                for (br1, br2) in seg_arr.as_ref().into_iter().zip(self.segment_arrays[x].as_ref().into_iter()) {
-                    if br1 != br2 { return Err((seg_arr, SegmentArrayTreeError::IncompatibleSegmentArrays(x))); }
-                }
-                // If the new one is larger, we insert it and return the old one
-                if seg_arr.as_ref().into_iter().len() > self.segment_arrays[x].as_ref().into_iter().len() {
-                    std::mem::swap(&mut seg_arr, &mut self.segment_arrays[x]);
-                    (Some(x), AddStatus::Replaced(seg_arr))
-                // Else, we don't need to add any segments to the tree
-                } else {
-                    (None, AddStatus::AlreadyContained(seg_arr))
+            Some((x, partial)) => {
+                match self.compatibility.compare(seg_arr.as_ref(), self.segment_arrays[x].as_ref()) {
+                    None if partial => { return Err((seg_arr, SegmentArrayTreeError::IntersectingSegment(x))); },
+                    None => { return Err((seg_arr, SegmentArrayTreeError::IncompatibleSegmentArrays(x))); },
+                    // If the new one is larger, we insert it instead of the old one, and return that
+                    Some(Ordering::Greater) => {
+                        std::mem::swap(&mut seg_arr, &mut self.segment_arrays[x]);
+                        for seg in seg_arr.as_ref().iter().map(|s| s.into()) {
+                            // These were inserted exactly when the old one was added
+                            self.tree.remove_segment(&seg).unwrap();
+                        }
+                        (Some(x), AddStatus::Replaced(seg_arr))
+                    },
+                    // Else, we don't need to add any segments to the tree
+                    Some(_) => (None, AddStatus::AlreadyContained(seg_arr)),
                 }
             }
         };
@@ -106,7 +172,7 @@ impl<M, I> SegmentArrayTree<M, I> where M: AsRef<[I]>, for<'a> &'a I: Into<Segme
     pub fn iter<'a>(&'a self) -> slice::Iter<'a, M> { self.segment_arrays.iter() }
 }
 
-impl<M, I> IntoIterator for SegmentArrayTree<M, I> {
+impl<M, I, C> IntoIterator for SegmentArrayTree<M, I, C> {
     type Item = M;
     type IntoIter = vec::IntoIter<M>;
     fn into_iter(self) -> Self::IntoIter { self.segment_arrays.into_iter() }
@@ -115,8 +181,9 @@ impl<M, I> IntoIterator for SegmentArrayTree<M, I> {
 
 #[cfg(test)]
 mod tests {
-    use super::{SegmentArrayTree, SegmentArrayTreeError, AddStatus};
+    use super::{SegmentArrayTree, SegmentArrayTreeError, AddStatus, Compatibility};
     use crate::segment_tree::Segment;
+    use std::cmp::Ordering;
     use std::collections::HashSet;
     use std::iter::FromIterator;
 
@@ -126,6 +193,7 @@ mod tests {
         pub num: u64,
     }
 
+    #[allow(clippy::needless_borrow)]
     impl AsRef<[Segment<u64>]> for SegmentVecAndInt {
         fn as_ref(&self) -> &[Segment<u64>] {
             &self.vec.as_ref()
@@ -170,6 +238,8 @@ mod tests {
             assert_eq!(sv.num, 70);
             assert_eq!(sat.get_by_idx(i).num, 30);
         });
+        assert_eq!(sat.all_intersecting(&build(vec![(2, 4), (16, 18)], 80)).into_iter().map(|i| sat.get_by_idx(i).num).collect::<Vec<_>>(), vec![10, 30]);
+        assert_eq!(sat.all_intersecting(&build(vec![(0, 1), (20, 30)], 90)), vec![]);
         assert_eq!(sat.iter().map(|sv| sv.num).collect::<HashSet<u64>>(), HashSet::<u64>::from_iter(vec![10, 30]));
         assert_eq!(sat.into_iter().map(|sv| sv.num).collect::<HashSet<u64>>(), HashSet::<u64>::from_iter(vec![10, 30]));
     }
@@ -196,6 +266,7 @@ mod tests {
         pub num: u64,
     }
 
+    #[allow(clippy::needless_borrow)]
     impl AsRef<[RichSegment]> for RichSegmentVecAndInt {
         fn as_ref(&self) -> &[RichSegment] {
             &self.vec.as_ref()
@@ -219,4 +290,46 @@ mod tests {
         });
 
     }
+
+    /// Allows the last segment of the shorter array to end earlier than its counterpart.
+    struct TrimmedLast;
+
+    impl Compatibility<Segment<u64>> for TrimmedLast {
+        fn compare(&self, new: &[Segment<u64>], old: &[Segment<u64>]) -> Option<Ordering> {
+            let n = new.len().min(old.len());
+            if n == 0 || new[..n - 1] != old[..n - 1] || new[n - 1].start != old[n - 1].start { return None; }
+            let ord = new.len().cmp(&old.len()).then(new[n - 1].end.cmp(&old[n - 1].end));
+            match new.len().cmp(&old.len()) {
+                Ordering::Greater if old[n - 1].end > new[n - 1].end => None,
+                Ordering::Less if new[n - 1].end > old[n - 1].end => None,
+                _ => Some(ord),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compatibility_rule() {
+        let mut sat = SegmentArrayTree::with_compatibility(TrimmedLast);
+        assert_eq!(sat.add(build(vec![(1, 3), (7, 9)], 0)), Ok(AddStatus::Added));
+        assert_eq!(sat.add(build(vec![(1, 3), (7, 10), (13, 15)], 10)), Ok(AddStatus::Replaced(build(vec![(1, 3), (7, 9)], 0))));
+        assert_eq!(sat.add(build(vec![(1, 3), (7, 10), (13, 14)], 20)), Ok(AddStatus::AlreadyContained(build(vec![(1, 3), (7, 10), (13, 14)], 20))));
+        assert_eq!(sat.add(build(vec![(1, 3), (7, 10), (13, 16)], 30)), Ok(AddStatus::Replaced(build(vec![(1, 3), (7, 10), (13, 15)], 10))));
+        // The old segments are gone from the tree
+        assert_eq!(sat.add(build(vec![(15, 16)], 40)), Err((build(vec![(15, 16)], 40), SegmentArrayTreeError::IntersectingSegment(0))));
+        assert_let!(Err((sv, SegmentArrayTreeError::IntersectingSegment(i))) = sat.add(build(vec![(2, 3)], 50)), {
+            assert_eq!(sv.num, 50);
+            assert_eq!(sat.get_by_idx(i).num, 30);
+        });
+        // Only the last shared segment may be trimmed
+        assert_let!(Err((sv, SegmentArrayTreeError::IntersectingSegment(i))) = sat.add(build(vec![(1, 3), (7, 9), (13, 16)], 60)), {
+            assert_eq!(sv.num, 60);
+            assert_eq!(sat.get_by_idx(i).num, 30);
+        });
+        assert_eq!(sat.iter().map(|sv| sv.num).collect::<Vec<u64>>(), vec![30]);
+
+        // The default rule still rejects a trimmed last segment
+        let mut sat = SegmentArrayTree::new();
+        assert_eq!(sat.add(build(vec![(1, 3), (7, 9)], 0)), Ok(AddStatus::Added));
+        assert_let!(Err((_, SegmentArrayTreeError::IntersectingSegment(0))) = sat.add(build(vec![(1, 3), (7, 10)], 10)));
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::btree_map::Entry as BEntry;
+use std::collections::btree_map::{Entry as BEntry, Range as BRange};
 use std::collections::BTreeMap;
 use std::mem;
 use core::ops::{RangeBounds, Bound};
@@ -43,6 +43,7 @@ impl<'a, T> RangeBounds<T> for RefRangeInclusive<'a, T> {
 
 struct RefRangeFromNonInclusive<'a, K>(&'a K);
 
+#[allow(clippy::needless_borrow)]
 impl<'a, K> RangeBounds<K> for RefRangeFromNonInclusive<'a, K> {
     fn start_bound(&self) -> Bound<&K> { Bound::Excluded(&self.0) }
     fn end_bound(&self) -> Bound<&K> { Bound::Unbounded }
@@ -50,6 +51,7 @@ impl<'a, K> RangeBounds<K> for RefRangeFromNonInclusive<'a, K> {
 
 struct RefRangeToInclusive<'a, K>(&'a K);
 
+#[allow(clippy::needless_borrow)]
 impl<'a, K> RangeBounds<K> for RefRangeToInclusive<'a, K> {
     fn start_bound(&self) -> Bound<&K> { Bound::Unbounded }
     fn end_bound(&self) -> Bound<&K> { Bound::Included(&self.0) }
@@ -57,6 +59,7 @@ impl<'a, K> RangeBounds<K> for RefRangeToInclusive<'a, K> {
 
 struct RefRangeTo<'a, K>(&'a K);
 
+#[allow(clippy::needless_borrow)]
 impl<'a, K> RangeBounds<K> for RefRangeTo<'a, K> {
     fn start_bound(&self) -> Bound<&K> { Bound::Unbounded }
     fn end_bound(&self) -> Bound<&K> { Bound::Excluded(&self.0) }
@@ -81,6 +84,7 @@ enum SegmentValue<V> {
     EndStart(V),
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<V> SegmentValue<V> {
     fn get_ref<'a>(&'a self) -> Option<&'a V> {
         match self {
@@ -99,6 +103,7 @@ impl<V> SegmentValue<V> {
 
 type InnerEntry<'a, K, V> = BEntry<'a, K, SegmentValue<V>>;
 
+#[allow(clippy::match_like_matches_macro)]
 fn remove_start<K: Ord + Debug + Clone, V>(tree: &mut BTree<K, V>, start: &K) {
     let val = tree.get_mut(start).unwrap();
     let remove: bool = if let SegmentValue::Start = val { true } else { false };
@@ -118,6 +123,7 @@ fn remove_start<K: Ord + Debug + Clone, V>(tree: &mut BTree<K, V>, start: &K) {
     }
 }
 
+#[allow(clippy::match_like_matches_macro)]
 fn remove_end<K: Ord + Debug + Clone, V>(tree: &mut BTree<K, V>, end: &K) -> V {
     let val = tree.get_mut(end).unwrap();
     let remove: bool = if let SegmentValue::End(_) = val { true } else { false };
@@ -324,9 +330,11 @@ macro_rules! impl_segment {
 }
 
 impl<K: Ord + Debug + Clone, V> SegmentTree<K, V> {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self { SegmentTree(BTreeMap::new()) }
 
     #[inline]
+    #[allow(clippy::match_like_matches_macro)]
     fn check_maybe_contained(&self, seg: &Segment<K>) -> bool {
         match self.0.range(RefRangeTo(&seg.start)).next_back() {
             Some((_, &SegmentValue::Start)) | Some((_, &SegmentValue::EndStart(_))) => true,
@@ -351,7 +359,7 @@ impl<K: Ord + Debug + Clone, V> SegmentTree<K, V> {
 
     /// Gets an Ok(Entry), Vacant or Occupied, if the tree doesn't contain any intersection with the
     /// segment or contains it exactly. Returns None otherwise.
-    pub fn entry_segment(&mut self, seg: Segment<K>) -> Result<Entry<K, V>, K> {
+    pub fn entry_segment(&mut self, seg: Segment<K>) -> Result<Entry<'_, K, V>, K> {
         Ok(if self.contains_segment(&seg)? {
             Entry::Occupied(OccupiedEntry { tree: self, seg })
         } else {
@@ -388,6 +396,65 @@ impl<K: Ord + Debug + Clone, V> SegmentTree<K, V> {
         };
         Some((Segment { start: start_idx.clone(), end: end_idx.clone() }, val))
     }
+
+    /// Iterates over all the segments in the tree, in order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.0.range(..), start: None, end: None, done: false }
+    }
+
+    /// Iterates, in order, over all the segments in the tree which intersect the given segment.
+    pub fn iter_intersecting(&self, seg: &Segment<K>) -> Iter<'_, K, V> {
+        let begin = match self.0.range(RefRangeToInclusive(&seg.start)).next_back() {
+            Some((k, SegmentValue::Start)) | Some((k, SegmentValue::EndStart(_))) => k,
+            _ => &seg.start,
+        };
+        Iter {
+            inner: self.0.range::<K, _>((Bound::Included(begin), Bound::Unbounded)),
+            start: None,
+            end: Some(seg.end.clone()),
+            done: false,
+        }
+    }
+}
+
+/// An iterator over the segments of a SegmentTree, with their values.
+pub struct Iter<'a, K, V> {
+    inner: BRange<'a, K, SegmentValue<V>>,
+    /// The start of the segment we are currently in, if any
+    start: Option<&'a K>,
+    /// Segments starting at or after this point are not yielded
+    end: Option<K>,
+    done: bool,
+}
+
+impl<'a, K: Ord + Clone, V> Iter<'a, K, V> {
+    fn starts_in_range(&self, point: &K) -> bool {
+        self.end.as_ref().map_or(true, |end| point < end)
+    }
+}
+
+impl<'a, K: Ord + Clone, V> Iterator for Iter<'a, K, V> {
+    type Item = (Segment<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let (point, val) = match self.inner.next() {
+                Some(x) => x,
+                None => { self.done = true; break; }
+            };
+            let new_start = match val {
+                SegmentValue::End(_) => None,
+                SegmentValue::Start | SegmentValue::EndStart(_) => {
+                    if self.starts_in_range(point) { Some(point) } else { self.done = true; None }
+                }
+            };
+            let prev_start = mem::replace(&mut self.start, new_start);
+            if let (Some(start), Some(v)) = (prev_start, val.get_ref()) {
+                return Some((Segment { start: start.clone(), end: point.clone() }, v));
+            }
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -454,6 +521,36 @@ mod tests {
         assert_eq!(st.get_containing_segment(&10), None);
     }
 
+    #[test]
+    fn iter() {
+        let mut st = SegmentTree::new();
+        assert_eq!(st.iter().next(), None);
+        assert_eq!(st.insert_segment(Segment::new(1, 3), X(0)), Ok(None));
+        assert_eq!(st.insert_segment(Segment::new(3, 5), X(1)), Ok(None));
+        assert_eq!(st.insert_segment(Segment::new(7, 9), X(2)), Ok(None));
+        assert_eq!(st.insert_segment(Segment::new(12, 15), X(3)), Ok(None));
+        assert_eq!(st.iter().collect::<Vec<_>>(), vec![
+            (Segment::new(1, 3), &X(0)),
+            (Segment::new(3, 5), &X(1)),
+            (Segment::new(7, 9), &X(2)),
+            (Segment::new(12, 15), &X(3)),
+        ]);
+        assert_eq!(st.iter_intersecting(&Segment::new(2, 8)).collect::<Vec<_>>(), vec![
+            (Segment::new(1, 3), &X(0)),
+            (Segment::new(3, 5), &X(1)),
+            (Segment::new(7, 9), &X(2)),
+        ]);
+        assert_eq!(st.iter_intersecting(&Segment::new(3, 7)).collect::<Vec<_>>(), vec![
+            (Segment::new(3, 5), &X(1)),
+        ]);
+        assert_eq!(st.iter_intersecting(&Segment::new(5, 7)).next(), None);
+        assert_eq!(st.iter_intersecting(&Segment::new(9, 12)).next(), None);
+        assert_eq!(st.iter_intersecting(&Segment::new(13, 14)).collect::<Vec<_>>(), vec![
+            (Segment::new(12, 15), &X(3)),
+        ]);
+        assert_eq!(st.iter_intersecting(&Segment::new(0, 100)).count(), 4);
+    }
+
     #[test]
     fn inside() {
         let mut st = SegmentTree::new();